@@ -1,206 +1,304 @@
 extern crate css_color_parser;
 extern crate docopt;
-//extern crate dxf;
 extern crate lasermidi;
 extern crate rimd;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde_json;
+extern crate toml;
 
 use docopt::Docopt;
 use rimd::SMF;
+use std::fmt;
 use std::fs::File;
-use std::io::{stdout, Write};
+use std::io::{self, stdout, Write};
 use std::path::Path;
+use std::process;
 
 use lasermidi::*;
 
-const USAGE: &'static str = "
+const USAGE: &str = "
 Usage:
+    lasermidi info [options] INPUT
+    lasermidi notes [options] INPUT
     lasermidi [options] INPUT [OUTPUT]
+    lasermidi [options] --print-config [INPUT]
     lasermidi (--help | --version)
 
-All measurements are in mm.
+The info command describes every track of the MIDI file and estimates the size of the tape.
+The notes command lists the notes that the selected tracks play, how often each is played, and
+which are missing from the music box, followed by a --notes value with exactly those notes.
+
+All measurements are in mm. Options that are not given take their values from --config, or
+else the defaults shown.
 
 Options:
     -h, --help  Show this message and exit.
     --version  Print the version and exit.
-    -t, --track-num <num>  Track number to process. [default: 1]
-    -n, --notes <notes>  Comma-separated list of MIDI note numbers supported by your music box.
-      [default: 40,42,44,45,46,47,48,49,50,51,52,53,54,55,56,57,58,\
-      59,60,61,62,63,64,66,68,69,71,73,78,80].
-    --tape-height <height>  Height of programming tape. [default: 68.6]
-    --space-above-top-row <space>  Space between edge of tape and first row.  [default: 6]
-    --space-below-bottom-row <space>  Space between last row and edge of tape.  [default: 5]
-    --space-before-first-note <space>  Space between end of lead-in and first note.  [default: 20]
-    --space-after-last-note <space>  Space between last note and end of tape.  [default: 20]
-    --space-between-strips <space>  Vertical space between two strips cut from the same page.
-        [default: 10]
-    --hole-diameter <diameter>  Diameter of each hole. [default: 2.4]
-    --page-width <width>  Width of the page. [default: 297]
-    --page-height <height>  Height of the page. [default: 210]
-    --margin-left <margin>  Left margin. [default: 10]
-    --margin-right <margin>  Right margin. [default: 10]
-    --margin-top <margin>  Top margin. [default: 10]
-    --margin-bottom <margin>  Bottom margin. [default: 10]
-    --cut-stroke-width <width>  Width of lines to be cut. Should equal the kerf. [default: 0.08]
-    --cut-color <color>  SVG color of lines to be cut. [default: red]
-    --engrave-color <color>  SVG color for engraving.  [default: black]
-    --stretch <factor>  Horizontal stretch factor (mm / beat). [default: 16]
-    --lead-in-width <width>  Width of diagonal edge at beginning of first page.  [default: 15]
-    --lead-in-height <width>  Height of diagonal edge at beginning of first page.  [default: 35]
-    --num-zig-zags <num>  Number of zig-zags in connecting edges.  [default: 5]
-    --join-width <width>  Width of connecting edge join.  [default: 5]
-    --join-style <style>  Straight, zigzag, or diagonal.  [default: zigzag]
-    --title <title>  Name of song.
-    --font-file <ttf>  Font to use for PDF format.
-    --output-format <format>  SVG, PDF, or JSON.
+    --print-config  Print the options as TOML, e.g. to save as a --config file, and exit. An
+      INPUT is needed to resolve --transpose auto and --notes song.";
+
+const EXIT_STATUS: &str = "
+Exit status:
+    1  Invalid usage.
+    2  An option has an invalid value, or a file it names could not be read.
+    3  The MIDI file could not be read.
+    4  The song could not be laid out on the tape.
+    5  Holes are too close together in --strict mode.
+    6  The output could not be written.
 ";
 
 #[derive(Debug, Deserialize)]
 #[allow(non_snake_case)]
 struct Args {
-    arg_INPUT: String,
+    cmd_info: bool,
+    cmd_notes: bool,
+    arg_INPUT: Option<String>,
     arg_OUTPUT: Option<String>,
-    flag_track_num: usize,
-    flag_notes: String,
-    flag_tape_height: f64,
-    flag_space_above_top_row: f64,
-    flag_space_below_bottom_row: f64,
-    flag_space_before_first_note: f64,
-    flag_space_after_last_note: f64,
-    flag_space_between_strips: f64,
-    flag_page_width: f64,
-    flag_page_height: f64,
-    flag_margin_left: f64,
-    flag_margin_top: f64,
-    flag_margin_right: f64,
-    flag_margin_bottom: f64,
-    flag_hole_diameter: f64,
-    flag_cut_stroke_width: f64,
-    flag_cut_color: String,
-    flag_engrave_color: String,
-    flag_stretch: f64,
-    flag_lead_in_width: f64,
-    flag_lead_in_height: f64,
-    flag_num_zig_zags: u16,
-    flag_join_width: f64,
-    flag_join_style: JoinStyle,
-    flag_title: String,
-    flag_output_format: Option<OutputFormat>,
-    flag_font_file: Option<String>,
+    flag_print_config: bool,
+}
+
+/// Why the program failed. Each kind of failure has its own exit code.
+#[derive(Debug)]
+enum Failure {
+    /// A command line argument could not be parsed.
+    InvalidArgument(String),
+    /// The MIDI file could not be read.
+    Input(String),
+    Lasermidi(Error),
+}
+
+impl Failure {
+    fn exit_code(&self) -> i32 {
+        match *self {
+            Failure::InvalidArgument(_) |
+            Failure::Lasermidi(Error::InvalidFlag(_)) |
+            Failure::Lasermidi(Error::InvalidPresetFile(_)) |
+            Failure::Lasermidi(Error::InvalidConfigFile(_)) |
+            Failure::Lasermidi(Error::Font(_)) |
+            Failure::Lasermidi(Error::InvalidOptions(_)) => 2,
+            Failure::Input(_) => 3,
+            Failure::Lasermidi(Error::HoleCollisions(_)) => 5,
+            Failure::Lasermidi(Error::Io(_)) |
+            Failure::Lasermidi(Error::Pdf(_)) |
+            Failure::Lasermidi(Error::Dxf(_)) => 6,
+            Failure::Lasermidi(_) => 4,
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Failure::InvalidArgument(ref e) => write!(f, "invalid argument: {}", e),
+            Failure::Input(ref e) => write!(f, "failed to load MIDI file: {}", e),
+            Failure::Lasermidi(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<Error> for Failure {
+    fn from(e: Error) -> Failure {
+        Failure::Lasermidi(e)
+    }
 }
 
 fn main() {
-    let args: Args = Docopt::new(USAGE)
-        .and_then(|d| {
-            d.help(true)
-                .version(Some(
-                    env!("CARGO_PKG_NAME").to_string() + " v" +
-                        env!("CARGO_PKG_VERSION"),
-                ))
-                .deserialize()
+    if let Err(failure) = run() {
+        eprintln!("Error: {}", failure);
+        process::exit(failure.exit_code());
+    }
+}
+
+fn run() -> Result<(), Failure> {
+    let docopt = Docopt::new(format!("{}{}{}", USAGE, FLAGS_USAGE, EXIT_STATUS))
+        .map(|d| {
+            d.help(true).version(Some(
+                env!("CARGO_PKG_NAME").to_string() + " v" +
+                    env!("CARGO_PKG_VERSION"),
+            ))
         })
         .unwrap_or_else(|e| e.exit());
+    let args: Args = docopt.deserialize().unwrap_or_else(|e| e.exit());
+    let flags: Flags = docopt.deserialize().unwrap_or_else(|e| e.exit());
 
-    let notes: Vec<u8> = args.flag_notes
-        .split(',')
-        .map(|x| x.parse().expect("Invalid note number"))
-        .collect();
-
-    let smf = SMF::from_file(&Path::new(&args.arg_INPUT[..])).expect("Failed to load MIDI file");
+    let smf = match args.arg_INPUT {
+        Some(ref input) => Some(SMF::from_file(Path::new(&input[..])).map_err(|e| {
+            Failure::Input(e.to_string())
+        })?),
+        None => None,
+    };
+    if args.cmd_info {
+        let smf = smf.ok_or_else(|| Failure::InvalidArgument("no INPUT file".to_string()))?;
+        print_info(&flags.options()?, &smf);
+        return Ok(());
+    }
+    let (options, transpositions) = flags.resolve(smf.as_ref())?;
+    for t in &transpositions {
+        eprintln!("{}", t);
+    }
+    if let Some(best) = transpositions.iter().find(|t| t.shift == options.transpose) {
+        eprintln!(
+            "Transposing by {:+} semitones ({} unplayable notes)",
+            best.shift,
+            best.unplayable
+        );
+    }
+    if args.cmd_notes {
+        let smf = smf.ok_or_else(|| Failure::InvalidArgument("no INPUT file".to_string()))?;
+        return print_notes(&options, &smf);
+    }
+    if args.flag_print_config {
+        let config = toml::to_string(&options).map_err(|e| {
+            Failure::InvalidArgument(format!("cannot print options: {}", e))
+        })?;
+        print!("{}", config);
+        return Ok(());
+    }
+    let smf = smf.ok_or_else(|| Failure::InvalidArgument("no INPUT file".to_string()))?;
     let output_pattern = args.arg_OUTPUT.clone();
-    let options = Options {
-        track_num: args.flag_track_num,
-        tape_height: args.flag_tape_height,
-        interior_margin_top: args.flag_space_above_top_row,
-        interior_margin_left: args.flag_space_before_first_note,
-        interior_margin_right: args.flag_space_after_last_note,
-        row_spacing: (args.flag_tape_height - args.flag_space_above_top_row -
-                          args.flag_space_below_bottom_row) /
-            (notes.len() as f64 - 1.0),
-        notes: notes,
-        page_width: args.flag_page_width,
-        page_height: args.flag_page_height,
-        margin_left: args.flag_margin_left,
-        margin_top: args.flag_margin_top,
-        margin_right: args.flag_margin_right,
-        margin_bottom: args.flag_margin_bottom,
-        gap: args.flag_space_between_strips,
-        hole_radius: args.flag_hole_diameter / 2.0,
-        cut_stroke_width: args.flag_cut_stroke_width,
-        cut_color: args.flag_cut_color.parse().expect(
-            "Failed to parse cut color",
-        ),
-        engrave_color: args.flag_engrave_color.parse().expect(
-            "Failed to parse engrave color",
-        ),
-        stretch: args.flag_stretch,
-        lead_in_width: args.flag_lead_in_width,
-        lead_in_height: args.flag_lead_in_height,
-        num_zig_zags: args.flag_num_zig_zags,
-        join_width: args.flag_join_width,
-        join_style: args.flag_join_style,
-        title: args.flag_title,
-        font_file: args.flag_font_file,
+    let output_format = flags.output_format(
+        output_pattern.as_ref().map(|o| &o[..]),
+        "JSON",
+    )?;
+    let (layout, report) = options.layout_with_report(smf)?;
+    for warning in report.warnings() {
+        eprintln!("Warning: {}", warning);
+    }
+    let mut make_output_stream = |page_num: usize| -> io::Result<Box<dyn Write>> {
+        Ok(match output_pattern {
+            Some(ref pattern) => {
+                let path = pattern.replace("%", &(page_num + 1).to_string());
+                Box::new(File::create(Path::new(&path[..])).map_err(|e| {
+                    io::Error::new(e.kind(), format!("{}: {}", path, e))
+                })?)
+            }
+            None => Box::new(stdout()),
+        })
     };
-    let output_format = args.flag_output_format.unwrap_or_else(|| {
-        output_pattern
-            .as_ref()
-            .map(|o| o.to_lowercase())
-            .map(|o| {
-                if o.ends_with(".json") {
-                    OutputFormat::JSON
-                } else if o.ends_with(".pdf") {
-                    OutputFormat::PDF
-                } else if o.ends_with(".svg") {
-                    OutputFormat::SVG
-                // } else if o.ends_with(".dxf") {
-                // OutputFormat::DXF
-                } else {
-                    OutputFormat::JSON
-                }
-            })
-            .unwrap_or(OutputFormat::JSON)
-    });
-    let layout = options.layout(smf).unwrap();
-    match output_format {
-        OutputFormat::SVG => {
-            options
-                .make_svg(&layout[..], &mut |page_num| match output_pattern {
-                    Some(ref pattern) => Box::new(
-                        File::create(Path::new(
-                            &pattern.replace("%", &(page_num + 1).to_string())[..],
-                        )).expect("Failed to open output file"),
-                    ),
-                    None => Box::new(stdout()),
-                })
-                .expect("Failed to write SVG to output file")
-        }
-        OutputFormat::JSON => {
-            let output: Box<Write> = match output_pattern {
-                Some(ref pattern) => {
-                    Box::new(File::create(Path::new(pattern)).expect(
-                        "Failed to open output file",
-                    ))
-                }
-                None => Box::new(stdout()),
-            };
-            serde_json::to_writer_pretty(output, &layout).expect("Failed to write output");
-        }
-        #[cfg(feature = "pdf")]
-        OutputFormat::PDF => {
-            let mut output: Box<Write> = match output_pattern {
-                Some(ref pattern) => {
-                    Box::new(File::create(Path::new(pattern)).expect(
-                        "Failed to open output file",
-                    ))
-                }
-                None => Box::new(stdout()),
-            };
-            options.make_pdf(&layout[..], &mut output).unwrap();
+    let mut renderer = (output_format.make_renderer)(&options, &mut make_output_stream);
+    render(&layout[..], &mut *renderer)?;
+    Ok(())
+}
+
+/// Prints how often each note is played and which notes are missing from `options.notes`.
+fn print_notes(options: &Options, smf: &SMF) -> Result<(), Failure> {
+    let counts = options.note_counts(smf)?;
+    let (highest, lowest) = match (counts.first(), counts.last()) {
+        (Some(highest), Some(lowest)) => (highest.note, lowest.note),
+        _ => return Err(Error::EmptyTrack.into()),
+    };
+    println!("Note  Pitch  Count");
+    for c in &counts {
+        println!(
+            "{:4}  {:5}  {:5}{}",
+            c.note,
+            note_name(c.note),
+            c.count,
+            if options.notes.contains(&c.note) {
+                ""
+            } else {
+                "  missing"
+            }
+        );
+    }
+    let missing: Vec<String> = counts
+        .iter()
+        .filter(|c| !options.notes.contains(&c.note))
+        .map(|c| format!("{} ({})", c.note, note_name(c.note)))
+        .collect();
+    let notes: Vec<String> = counts.iter().map(|c| c.note.to_string()).collect();
+    println!();
+    println!(
+        "{} distinct notes from {} ({}) up to {} ({})",
+        counts.len(),
+        note_name(lowest),
+        lowest,
+        note_name(highest),
+        highest
+    );
+    if missing.is_empty() {
+        println!("Missing from the music box: none");
+    } else {
+        println!("Missing from the music box: {}", missing.join(", "));
+    }
+    println!("--notes {}", notes.join(","));
+    Ok(())
+}
+
+/// Prints a summary of each track and of the tape the song needs with `options`.
+fn print_info(options: &Options, smf: &SMF) {
+    let info = midi_info(smf);
+    if info.division > 0 {
+        println!("Division: {} ticks per beat", info.division);
+    } else {
+        println!(
+            "Division: {} frames per second, {} ticks per frame (SMPTE)",
+            -((info.division >> 8) as i8 as i16),
+            info.division & 0xff
+        );
+    }
+    print!("Duration: {} ticks", info.duration);
+    if let Some(beats) = info.duration_beats {
+        print!(", {:.2} beats", beats);
+    }
+    if let Some(seconds) = info.duration_seconds {
+        print!(", {:.2} seconds", seconds);
+    }
+    println!();
+    println!();
+    // Durations that cannot be worked out for this time division are left blank.
+    let optional = |value: Option<f64>| value.map_or(String::new(), |v| format!("{:.2}", v));
+    println!("Track  Notes     Ticks     Beats   Seconds  Range         Channels (notes)  Name");
+    for (i, track) in info.tracks.iter().enumerate() {
+        let range = match track.note_range {
+            Some((highest, lowest)) => format!("{} to {}", note_name(lowest), note_name(highest)),
+            None => String::new(),
+        };
+        let channels: Vec<String> = track
+            .channels
+            .iter()
+            .map(|&(channel, notes)| format!("{} ({})", channel, notes))
+            .collect();
+        println!(
+            "{:5}  {:5}  {:8}  {:>8}  {:>8}  {:12}  {:16}  {}",
+            i,
+            track.notes,
+            track.duration,
+            optional(track.duration_beats),
+            optional(track.duration_seconds),
+            range,
+            channels.join(", "),
+            track.name.as_ref().map_or("", |name| &name[..])
+        );
+    }
+    println!();
+    println!("Tempo changes:");
+    if info.tempo_changes.is_empty() {
+        println!("  none (120 bpm)");
+    }
+    for change in &info.tempo_changes {
+        println!("  tick {}: {:.2} bpm", change.tick, change.beats_per_minute);
+    }
+    println!("Time signatures:");
+    if info.time_signatures.is_empty() {
+        println!("  none (4/4)");
+    }
+    for signature in &info.time_signatures {
+        println!(
+            "  tick {}: {}/{}",
+            signature.tick,
+            signature.numerator,
+            signature.denominator
+        );
+    }
+    println!();
+    match options.tape_size(smf) {
+        Ok(size) => {
+            println!("Tape length: {:.1}mm", size.length);
+            println!("Strips: {}", size.strips);
+            println!("Pages: {}", size.pages);
         }
-        #[cfg(not(feature = "pdf"))]
-        OutputFormat::PDF => panic!("pdf support was disabled at compile time"),
+        Err(e) => println!("The tape cannot be laid out with these options: {}", e),
     }
 }
@@ -1,50 +1,308 @@
 // TODO:
-// - Use Failure and remove the last few calls to unwrap() and expect()
-// - Draw grid
 // - Warn or fail if output pattern doesn't contain % and num_pages > 1
-// - Check that track contains at least one note
-// - Support printing --notes value based on notes in a MIDI file
 // - Feature gate PDF support (because it adds tons of deps)
-// - Support DXF output
-// - PDF: Expand outline to get precise size specified
 // - Write tests
 // - Support multi-page SVG if output pattern doesn't contain %
 // - Add a user-friendly web interface
 
 extern crate css_color_parser;
-//extern crate dxf;
+#[cfg(feature = "dxf")]
+extern crate dxf;
 #[cfg(feature = "pdf")]
 #[macro_use]
 extern crate printpdf;
 extern crate rimd;
+extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde_json;
+extern crate toml;
 
 use css_color_parser::Color;
-use rimd::{Event, Status, SMF};
-#[cfg(feature = "pdf")]
+use rimd::{Event, MetaCommand, Status, SMF};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::cmp::Reverse;
 use std::fs::File;
-use std::io::{self, Write};
+use std::{error, fmt};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
 
-#[derive(Debug, Deserialize, Eq, Copy, Clone, PartialEq)]
-pub enum OutputFormat {
-    SVG,
-    PDF,
-    JSON,
-    // DXF,
-}
+/// Microseconds per beat (120 BPM) until the first Set Tempo event.
+const DEFAULT_TEMPO: u64 = 500_000;
+
+const MICROS_PER_SECOND: f64 = 1_000_000.0;
+
+/// Beats per bar (4/4 time) until the first Time Signature event.
+const DEFAULT_BAR_LENGTH: f64 = 4.0;
 
-#[derive(Debug, Deserialize, Eq, Copy, Clone, PartialEq)]
+/// Control point distance (as a fraction of the radius) for approximating a quarter circle with a
+/// cubic Bezier curve.
+#[cfg(feature = "pdf")]
+const BEZIER_CIRCLE_KAPPA: f64 = 0.552_284_749_831;
+
+#[derive(Debug, Serialize, Deserialize, Eq, Copy, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
 pub enum JoinStyle {
     ZigZag,
     Diagonal,
     Straight,
 }
 
+/// Which notes of a chord to keep when it has more than `Options::max_polyphony` notes.
+#[derive(Debug, Serialize, Deserialize, Eq, Copy, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChordStrategy {
+    /// Keep the highest notes.
+    Highest,
+    /// Keep the lowest notes.
+    Lowest,
+    /// Keep the highest note (usually the melody) and the lowest (the bass), then fill from the
+    /// top down.
+    Melody,
+}
+
+/// Spacing of the grid that note times are snapped to.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Grid {
+    /// A fraction of a whole note, e.g. 16 for sixteenth notes.
+    NoteValue(u32),
+    /// A fixed number of ticks.
+    Ticks(u64),
+}
+
+impl Grid {
+    /// Returns the grid spacing in ticks for a file with the given time division.
+    fn ticks(&self, division: i16) -> Result<f64, Error> {
+        match *self {
+            // Time-code based files have no notion of note values.
+            Grid::NoteValue(_) if division <= 0 => Err(Error::UnsupportedDiv),
+            Grid::NoteValue(n) => Ok(division as f64 * 4.0 / n as f64),
+            Grid::Ticks(n) => Ok(n as f64),
+        }
+    }
+}
+
+impl FromStr for Grid {
+    type Err = String;
+
+    /// Parses a note value such as `1/16` or a number of ticks such as `120`.
+    fn from_str(s: &str) -> Result<Grid, String> {
+        let grid = if let Some(n) = s.strip_prefix("1/") {
+            n.parse().ok().map(Grid::NoteValue)
+        } else {
+            s.parse().ok().map(Grid::Ticks)
+        };
+        match grid {
+            Some(Grid::NoteValue(0)) | Some(Grid::Ticks(0)) | None => {
+                Err(format!("Invalid grid: {}", s))
+            }
+            Some(grid) => Ok(grid),
+        }
+    }
+}
+
+impl fmt::Display for Grid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Grid::NoteValue(n) => write!(f, "1/{}", n),
+            Grid::Ticks(n) => write!(f, "{}", n),
+        }
+    }
+}
+
+impl Serialize for Grid {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Grid {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Grid, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(de::Error::custom)
+    }
+}
+
+/// A point in a song, used to select part of it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Position {
+    /// The start of a bar, counting from 1 and following time signature changes.
+    Bar(u32),
+    /// The start of a beat (quarter note), counting from 1.
+    Beat(u32),
+    /// A time in ticks.
+    Tick(u64),
+}
+
+impl Position {
+    /// Converts the position to ticks, or fails for time-code based files, which have no bars or
+    /// beats.
+    fn ticks(&self, smf: &SMF) -> Result<u64, Error> {
+        let div = smf.division;
+        match *self {
+            Position::Tick(tick) => Ok(tick),
+            _ if div <= 0 => Err(Error::UnsupportedDiv),
+            // Bars and beats count from 1, so 0 is treated as 1 rather than underflowing.
+            Position::Beat(beat) => Ok((beat as u64).saturating_sub(1) * div as u64),
+            Position::Bar(bar) => Ok(bar_lines(smf).nth((bar as usize).saturating_sub(1)).unwrap()),
+        }
+    }
+}
+
+impl FromStr for Position {
+    type Err = String;
+
+    /// Parses positions like `17bar`, `65beat` or `30720tick`.
+    fn from_str(s: &str) -> Result<Position, String> {
+        let position = if let Some(n) = s.strip_suffix("bar") {
+            n.parse().ok().map(Position::Bar)
+        } else if let Some(n) = s.strip_suffix("beat") {
+            n.parse().ok().map(Position::Beat)
+        } else if let Some(n) = s.strip_suffix("tick") {
+            n.parse().ok().map(Position::Tick)
+        } else {
+            None
+        };
+        match position {
+            Some(Position::Bar(0)) | Some(Position::Beat(0)) | None => {
+                Err(format!("Invalid position: {}", s))
+            }
+            Some(position) => Ok(position),
+        }
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Position::Bar(n) => write!(f, "{}bar", n),
+            Position::Beat(n) => write!(f, "{}beat", n),
+            Position::Tick(n) => write!(f, "{}tick", n),
+        }
+    }
+}
+
+impl Serialize for Position {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Position {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Position, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(de::Error::custom)
+    }
+}
+
+/// What the length of a loop tape is rounded up to.
+#[derive(Debug, Serialize, Deserialize, Eq, Copy, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LoopPadding {
+    Beat,
+    Bar,
+}
+
+impl LoopPadding {
+    /// Returns where a loop starting at `start` ends: the first whole number of beats from
+    /// `start`, or the first bar line, at or after `min_end`.
+    fn end(&self, smf: &SMF, start: u64, min_end: u64) -> Result<u64, Error> {
+        let div = smf.division;
+        if div <= 0 {
+            // Time-code based files have no bars or beats.
+            return Err(Error::UnsupportedDiv);
+        }
+        Ok(match *self {
+            LoopPadding::Beat => {
+                let div = div as u64;
+                start + (min_end - start + div - 1) / div * div
+            }
+            LoopPadding::Bar => bar_lines(smf).find(|&bar| bar >= min_end).unwrap(),
+        })
+    }
+}
+
+/// How the note of each hole is labeled.
+#[derive(Debug, Serialize, Deserialize, Eq, Copy, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LabelStyle {
+    /// Scientific pitch names, e.g. `C4`.
+    Pitch,
+    /// Fixed-do solfège names, e.g. `Do`.
+    Solfege,
+}
+
+impl LabelStyle {
+    /// Returns the label for a note numbered as in `Options::notes`.
+    pub fn label(&self, note: u8) -> String {
+        const SOLFEGE: [&str; 12] = [
+            "Do",
+            "Do#",
+            "Re",
+            "Re#",
+            "Mi",
+            "Fa",
+            "Fa#",
+            "Sol",
+            "Sol#",
+            "La",
+            "La#",
+            "Si",
+        ];
+        match *self {
+            LabelStyle::Pitch => note_name(note),
+            LabelStyle::Solfege => SOLFEGE[(midi_note(note) % 12) as usize].to_string(),
+        }
+    }
+}
+
+/// What to do with notes that do not appear in `Options::notes`.
+#[derive(Debug, Serialize, Deserialize, Eq, Copy, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MissingNotePolicy {
+    /// Fail with `Error::InvalidNote`.
+    Fail,
+    /// Leave the note out.
+    Drop,
+    /// Move the note by as few octaves as possible to an available note, or drop it if there is
+    /// none. Of two notes equally far away, the lower one is used.
+    Fold,
+    /// Replace the note with the closest available note, or the lower of two equally close.
+    Nearest,
+}
+
+/// Everything about how a song is laid out and rendered. Options can be stored in TOML or JSON
+/// files with the same field names; fields left out of a file keep their default values.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Options {
-    pub track_num: usize,
+    /// Tracks whose notes are merged into the layout, or `None` for all tracks.
+    #[serde(with = "all_or_list")]
+    pub tracks: Option<Vec<usize>>,
+    /// MIDI channels (1-16) to read notes from, or `None` for all channels.
+    #[serde(with = "all_or_list")]
+    pub channels: Option<Vec<u8>>,
+    /// NoteOn events quieter than this are ignored. A velocity of zero is always treated as a
+    /// note-off.
+    pub min_velocity: u8,
+    /// The note of each row from the top of the tape, numbered as in `midi_note`.
     pub notes: Vec<u8>,
+    /// Semitones to shift every note by before looking up its row.
+    pub transpose: i8,
+    pub missing_notes: MissingNotePolicy,
+    /// Most notes to play at once, or `None` for no limit.
+    pub max_polyphony: Option<usize>,
+    pub chord_strategy: ChordStrategy,
+    /// Notes starting within this distance (in mm of tape) of the first note of a chord are part
+    /// of the chord.
+    pub chord_window: f64,
+    /// Holes in the same row closer than this (center to center) are reported as collisions,
+    /// e.g. to leave a comb tooth time to re-damp. Holes closer than `2 * hole_radius` merge when
+    /// cut and are always reported.
+    pub min_hole_distance: f64,
+    /// Fail with `Error::HoleCollisions` instead of reporting collisions.
+    pub strict: bool,
+    /// Mark colliding holes in SVG output.
+    pub highlight_collisions: bool,
     pub tape_height: f64,
     pub interior_margin_top: f64,
     pub interior_margin_left: f64,
@@ -59,129 +317,1142 @@ pub struct Options {
     pub gap: f64,
     pub hole_radius: f64,
     pub cut_stroke_width: f64,
+    #[serde(with = "css_color")]
     pub cut_color: Color,
+    #[serde(with = "css_color")]
     pub engrave_color: Color,
+    /// Color of the marks over colliding holes. It differs from `cut_color` and `engrave_color` so
+    /// that a laser cutter ignores the marks.
+    #[serde(with = "css_color")]
+    pub highlight_color: Color,
+    /// Engrave a line along each row and across the tape at each beat, heavier at each bar.
+    pub draw_grid: bool,
+    /// Engrave the name of each row's note in the space before the first note. Only the first
+    /// strip has that space, so later strips and loops are not labeled.
+    pub note_labels: bool,
+    /// Engrave a small label with the note of each hole next to it.
+    pub hole_labels: Option<LabelStyle>,
     pub stretch: f64,
+    /// Only notes at or after this position are placed, and the tape starts here.
+    pub start: Option<Position>,
+    /// Only notes before this position are placed, and the tape extends at least this far.
+    pub end: Option<Position>,
+    /// Snap note times to this grid before placing holes.
+    pub quantize: Option<Grid>,
+    /// Delay every other grid position by this fraction of the grid spacing, e.g. 1/3 for
+    /// triplet swing. Only used with `quantize`.
+    pub swing: f64,
+    /// Space notes by real elapsed time, following the file's tempo changes. `stretch` is then in
+    /// mm per second instead of mm per beat.
+    pub tempo_map: bool,
     pub lead_in_width: f64,
     pub lead_in_height: f64,
     pub num_zig_zags: u16,
     pub join_width: f64,
     pub join_style: JoinStyle,
+    /// Make a closed loop tape whose ends join each other, with its length padded to a whole
+    /// number of beats or bars. `None` makes a tape with a lead-in and a flat end.
+    pub loop_padding: Option<LoopPadding>,
     pub title: String,
     pub font_file: Option<String>,
 }
 
-#[derive(Eq, PartialEq, Ord, PartialOrd, Copy, Clone, Hash, Debug)]
-struct Note {
-    time: u64,
-    note: u8,
+impl Default for Options {
+    /// Options for the 30-note music box on A4 paper in landscape.
+    fn default() -> Options {
+        let notes = vec![
+            40,
+            42,
+            44,
+            45,
+            46,
+            47,
+            48,
+            49,
+            50,
+            51,
+            52,
+            53,
+            54,
+            55,
+            56,
+            57,
+            58,
+            59,
+            60,
+            61,
+            62,
+            63,
+            64,
+            66,
+            68,
+            69,
+            71,
+            73,
+            78,
+            80,
+        ];
+        Options {
+            tracks: Some(vec![1]),
+            channels: None,
+            min_velocity: 1,
+            transpose: 0,
+            missing_notes: MissingNotePolicy::Fail,
+            max_polyphony: None,
+            chord_strategy: ChordStrategy::Melody,
+            chord_window: 0.0,
+            min_hole_distance: 0.0,
+            strict: false,
+            highlight_collisions: false,
+            tape_height: 68.6,
+            interior_margin_top: 6.0,
+            interior_margin_left: 20.0,
+            interior_margin_right: 20.0,
+            row_spacing: (68.6 - 6.0 - 5.0) / (notes.len() as f64 - 1.0),
+            notes,
+            page_width: 297.0,
+            page_height: 210.0,
+            margin_left: 10.0,
+            margin_top: 10.0,
+            margin_right: 10.0,
+            margin_bottom: 10.0,
+            gap: 10.0,
+            hole_radius: 1.2,
+            cut_stroke_width: 0.08,
+            cut_color: Color {
+                r: 255,
+                g: 0,
+                b: 0,
+                a: 1.0,
+            },
+            engrave_color: Color {
+                r: 0,
+                g: 0,
+                b: 0,
+                a: 1.0,
+            },
+            highlight_color: Color {
+                r: 255,
+                g: 165,
+                b: 0,
+                a: 1.0,
+            },
+            draw_grid: false,
+            note_labels: false,
+            hole_labels: None,
+            stretch: 16.0,
+            start: None,
+            end: None,
+            quantize: None,
+            swing: 0.0,
+            tempo_map: false,
+            lead_in_width: 15.0,
+            lead_in_height: 35.0,
+            num_zig_zags: 5,
+            join_width: 5.0,
+            join_style: JoinStyle::ZigZag,
+            loop_padding: None,
+            title: String::new(),
+            font_file: None,
+        }
+    }
+}
+
+/// Stores colors as CSS color strings, e.g. `rgba(255, 0, 0, 1)`, and reads any CSS color.
+mod css_color {
+    use css_color_parser::Color;
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(color: &Color, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&format_args!(
+            "rgba({}, {}, {}, {})",
+            color.r,
+            color.g,
+            color.b,
+            color.a
+        ))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Color, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(|_| {
+            de::Error::custom("invalid CSS color")
+        })
+    }
+}
+
+/// Stores `None` as `"all"`, so that it is not confused with a field left out of a file.
+mod all_or_list {
+    use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum AllOrList<T> {
+        All(String),
+        List(Vec<T>),
+    }
+
+    pub fn serialize<T, S>(list: &Option<Vec<T>>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: Serialize,
+        S: Serializer,
+    {
+        match *list {
+            Some(ref list) => list.serialize(serializer),
+            None => serializer.serialize_str("all"),
+        }
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<Option<Vec<T>>, D::Error>
+    where
+        T: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        match AllOrList::deserialize(deserializer)? {
+            AllOrList::All(ref s) if s.eq_ignore_ascii_case("all") => Ok(None),
+            AllOrList::All(s) => Err(de::Error::custom(format!("expected \"all\", got {}", s))),
+            AllOrList::List(list) => Ok(Some(list)),
+        }
+    }
+}
+
+/// The notes selected from a MIDI file.
+struct Song {
+    /// Time units per unit of `Options::stretch`.
+    div: f64,
+    /// Sorted by time, with times relative to the start of the tape.
+    notes: Vec<Note>,
+    /// The earliest time at which the tape may end.
+    end: u64,
+    /// Times of the beats in the song (only if `Options::draw_grid` is set) and whether each one
+    /// starts a bar.
+    beats: Vec<(u64, bool)>,
+}
+
+/// Width of tape (in mm) available for notes on each kind of strip.
+struct UsableWidths {
+    first_strip: f64,
+    middle_strip: f64,
+    last_strip: f64,
+    /// When the whole song fits on one strip.
+    only_strip: f64,
+}
+
+/// A note to be played by the tape.
+#[derive(Eq, PartialEq, Ord, PartialOrd, Copy, Clone, Hash, Debug, Serialize)]
+pub struct Note {
+    /// Time from the start of the tape, in ticks (or microseconds with `Options::tempo_map`).
+    pub time: u64,
+    /// Numbered as in `Options::notes`.
+    pub note: u8,
+    /// Time of the note in the MIDI file, in ticks.
+    pub tick: u64,
+}
+
+impl Note {
+    pub fn midi_note(&self) -> u8 {
+        midi_note(self.note)
+    }
 }
 
+/// A point on a page, in mm from its top left corner.
 pub type Point = (f64, f64);
 
 #[derive(PartialEq, Clone, Debug, Serialize)]
 pub struct Text {
-    position: Point,
-    text: String,
-    font_size: f64,
+    /// The left end of the text's baseline.
+    pub position: Point,
+    pub text: String,
+    pub font_size: f64,
+}
+
+/// An engraved reference line along a row or across the tape at a beat.
+#[derive(PartialEq, Clone, Debug, Serialize)]
+pub struct GridLine {
+    pub start: Point,
+    pub end: Point,
+    /// Bar lines are drawn heavier than beat and row lines.
+    pub bar: bool,
+}
+
+#[derive(PartialEq, Copy, Clone, Debug, Serialize)]
+pub struct Hole {
+    /// The center of the hole.
+    pub position: Point,
+    /// The note played, numbered as in `Options::notes`.
+    pub note: u8,
+    /// Index of the note in `Options::notes`.
+    pub row: usize,
+    /// Time of the note in the MIDI file, in ticks.
+    pub tick: u64,
+    /// Index of the strip the hole is on, counting across pages.
+    pub strip: usize,
+    /// Distance from the start of the tape to the center of the hole, in mm, as if the tape were
+    /// one long strip.
+    pub offset: f64,
+}
+
+impl Hole {
+    pub fn midi_note(&self) -> u8 {
+        midi_note(self.note)
+    }
 }
 
 #[derive(PartialEq, Clone, Debug, Serialize)]
 pub struct Strip {
-    texts: Vec<Text>,
-    outline: Vec<Point>,
-    holes: Vec<Point>,
+    pub texts: Vec<Text>,
+    /// The closed outline to cut the strip out along.
+    pub outline: Vec<Point>,
+    pub holes: Vec<Hole>,
+    pub grid: Vec<GridLine>,
 }
 
 #[derive(PartialEq, Clone, Debug, Serialize)]
 pub struct Page {
-    strips: Vec<Strip>,
+    pub strips: Vec<Strip>,
+}
+
+/// A note that was not in `Options::notes` and what was done with it.
+#[derive(Eq, PartialEq, Copy, Clone, Debug, Serialize)]
+pub struct Substitution {
+    /// Time of the note in the MIDI file, in ticks.
+    pub tick: u64,
+    /// The note as it would have been played, after transposition.
+    pub original: u8,
+    /// The note played instead, or `None` if the note was dropped.
+    pub replacement: Option<u8>,
+}
+
+/// A note left out to keep a chord within `Options::max_polyphony`.
+#[derive(Eq, PartialEq, Copy, Clone, Debug, Serialize)]
+pub struct DroppedNote {
+    /// Time of the note in the MIDI file, in ticks.
+    pub tick: u64,
+    pub note: u8,
+}
+
+/// Two holes in the same row that are too close together.
+#[derive(PartialEq, Copy, Clone, Debug, Serialize)]
+pub struct Collision {
+    pub page: usize,
+    /// Index of the strip the holes are on, counting across pages.
+    pub strip: usize,
+    /// Index of the row in `Options::notes`.
+    pub row: usize,
+    pub first: Point,
+    pub second: Point,
+    /// Center to center distance between the holes.
+    pub distance: f64,
+}
+
+/// Everything that was changed from the original song to make it fit the tape, and any problems
+/// found with the result.
+#[derive(PartialEq, Clone, Debug, Default, Serialize)]
+pub struct Report {
+    pub substitutions: Vec<Substitution>,
+    pub dropped_notes: Vec<DroppedNote>,
+    pub collisions: Vec<Collision>,
+}
+
+impl Report {
+    /// Describes each entry of the report in one line, for front ends to show to the user.
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        for s in &self.substitutions {
+            warnings.push(match s.replacement {
+                Some(n) => format!("note {} at tick {} replaced with {}", s.original, s.tick, n),
+                None => format!("note {} at tick {} dropped", s.original, s.tick),
+            });
+        }
+        for d in &self.dropped_notes {
+            warnings.push(format!("note {} at tick {} dropped from chord", d.note, d.tick));
+        }
+        for c in &self.collisions {
+            warnings.push(format!(
+                "holes in row {} are {:.2}mm apart at x={:.2} on strip {} (page {})",
+                c.row + 1,
+                c.distance,
+                c.first.0,
+                c.strip + 1,
+                c.page + 1
+            ));
+        }
+        warnings
+    }
+}
+
+/// The result of trying one transposition of a song.
+#[derive(Eq, PartialEq, Copy, Clone, Debug, Serialize)]
+pub struct Transposition {
+    /// Semitones to shift every note by.
+    pub shift: i8,
+    /// How many of the song's notes are not in `Options::notes` after shifting.
+    pub unplayable: usize,
 }
 
-#[derive(Eq, PartialEq, Debug)]
+impl fmt::Display for Transposition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:+3} semitones: {} unplayable notes", self.shift, self.unplayable)
+    }
+}
+
+/// How many times one note is played in a song.
+#[derive(Eq, PartialEq, Copy, Clone, Debug, Serialize)]
+pub struct NoteCount {
+    /// Numbered as in `Options::notes`.
+    pub note: u8,
+    pub count: usize,
+}
+
+/// A summary of one track of a MIDI file.
+#[derive(PartialEq, Clone, Debug, Serialize)]
+pub struct TrackInfo {
+    /// From the track's name meta event, if it has one.
+    pub name: Option<String>,
+    /// Each channel (1-16) that plays notes on this track, with how many notes it plays.
+    pub channels: Vec<(u8, usize)>,
+    pub notes: usize,
+    /// The highest and lowest notes played, numbered as in `Options::notes`.
+    pub note_range: Option<(u8, u8)>,
+    /// Time of the track's last event in ticks.
+    pub duration: u64,
+    /// `duration` in beats, or `None` for time-code based files.
+    pub duration_beats: Option<f64>,
+    /// `duration` in seconds, or `None` if the time division is not supported.
+    pub duration_seconds: Option<f64>,
+}
+
+/// A change of tempo in a MIDI file.
+#[derive(PartialEq, Copy, Clone, Debug, Serialize)]
+pub struct TempoChange {
+    pub tick: u64,
+    pub beats_per_minute: f64,
+}
+
+/// A change of time signature in a MIDI file.
+#[derive(Eq, PartialEq, Copy, Clone, Debug, Serialize)]
+pub struct TimeSignature {
+    pub tick: u64,
+    pub numerator: u8,
+    pub denominator: u32,
+}
+
+impl TimeSignature {
+    /// Length of a bar in beats (quarter notes).
+    pub fn beats_per_bar(&self) -> f64 {
+        self.numerator as f64 * 4.0 / self.denominator as f64
+    }
+}
+
+/// A summary of a MIDI file, for finding the tracks and channels worth laying out.
+#[derive(PartialEq, Clone, Debug, Serialize)]
+pub struct MidiInfo {
+    pub division: i16,
+    pub tracks: Vec<TrackInfo>,
+    /// Time of the last event on any track in ticks.
+    pub duration: u64,
+    /// `duration` in beats, or `None` for time-code based files.
+    pub duration_beats: Option<f64>,
+    /// `duration` in seconds, or `None` if the time division is not supported.
+    pub duration_seconds: Option<f64>,
+    pub tempo_changes: Vec<TempoChange>,
+    pub time_signatures: Vec<TimeSignature>,
+}
+
+/// The size of the tape a song is laid out on.
+#[derive(PartialEq, Copy, Clone, Debug, Serialize)]
+pub struct TapeSize {
+    /// Length of the whole tape, or of one turn of a loop.
+    pub length: f64,
+    pub strips: usize,
+    pub pages: usize,
+}
+
+/// The notes and tape dimensions of a model of music box.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BoxPreset {
+    pub name: String,
+    /// Numbered as in `Options::notes`.
+    pub notes: Vec<u8>,
+    pub tape_height: f64,
+    /// Space between the top edge of the tape and the center of the first row.
+    pub space_above_top_row: f64,
+    /// Space between the center of the last row and the bottom edge of the tape.
+    pub space_below_bottom_row: f64,
+    pub hole_diameter: f64,
+    /// A comfortable `Options::stretch` for the box.
+    pub stretch: f64,
+}
+
+/// Returns the built-in presets. The 15- and 20-note presets use the dimensions of common paper
+/// strip boxes, which vary between manufacturers, so measure your box before cutting.
+pub fn box_presets() -> Vec<BoxPreset> {
+    vec![
+        BoxPreset {
+            name: "15-note".to_string(),
+            // Two octaves of C major from C4.
+            notes: vec![44, 45, 47, 49, 51, 52, 54, 56, 57, 59, 61, 63, 64, 66, 68],
+            tape_height: 41.0,
+            space_above_top_row: 6.5,
+            space_below_bottom_row: 6.5,
+            hole_diameter: 2.0,
+            stretch: 16.0,
+        },
+        BoxPreset {
+            name: "20-note".to_string(),
+            // C major from C4 to A6.
+            notes: vec![
+                35,
+                37,
+                39,
+                40,
+                42,
+                44,
+                45,
+                47,
+                49,
+                51,
+                52,
+                54,
+                56,
+                57,
+                59,
+                61,
+                63,
+                64,
+                66,
+                68,
+            ],
+            tape_height: 57.0,
+            space_above_top_row: 6.0,
+            space_below_bottom_row: 6.0,
+            hole_diameter: 2.4,
+            stretch: 16.0,
+        },
+        BoxPreset {
+            name: "30-note".to_string(),
+            // Chromatic from C3 to E6, leaving out a few of the highest and lowest semitones.
+            notes: vec![
+                40,
+                42,
+                44,
+                45,
+                46,
+                47,
+                48,
+                49,
+                50,
+                51,
+                52,
+                53,
+                54,
+                55,
+                56,
+                57,
+                58,
+                59,
+                60,
+                61,
+                62,
+                63,
+                64,
+                66,
+                68,
+                69,
+                71,
+                73,
+                78,
+                80,
+            ],
+            tape_height: 68.6,
+            space_above_top_row: 6.0,
+            space_below_bottom_row: 5.0,
+            hole_diameter: 2.4,
+            stretch: 16.0,
+        },
+    ]
+}
+
+/// Reads presets from a TOML file containing a `[[box]]` table for each one.
+pub fn load_box_presets(path: &Path) -> Result<Vec<BoxPreset>, Error> {
+    #[derive(Deserialize)]
+    struct PresetFile {
+        #[serde(rename = "box")]
+        boxes: Vec<BoxPreset>,
+    }
+    let mut contents = String::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut contents))
+        .map_err(|e| Error::InvalidPresetFile(format!("{}: {}", path.display(), e)))?;
+    toml::from_str::<PresetFile>(&contents)
+        .map(|file| file.boxes)
+        .map_err(|e| Error::InvalidPresetFile(format!("{}: {}", path.display(), e)))
+}
+
+/// Descriptions of the flags in `Flags`, in docopt format, to be included in the options
+/// section of a front end's usage message.
+pub const FLAGS_USAGE: &str = "
+    --config <file>  Read options from a TOML file, or a JSON file if its name ends in .json.
+    -t, --tracks <tracks>  Comma-separated list of track numbers to merge, or all. (default: 1)
+    --track-num <num>  Deprecated alias of --tracks.
+    -c, --channels <channels>  Comma-separated list of MIDI channels (1-16) to read notes from.
+      (default: all)
+    --min-velocity <velocity>  Ignore notes played more quietly than this (1-127). (default: 1)
+    --box <name>  Music box model to make the tape for: 15-note, 20-note, 30-note, or the name
+      of a preset in --box-file. (default: 30-note)
+    --box-file <file>  TOML file of custom music box presets, each in a [[box]] table.
+    -n, --notes <notes>  Comma-separated list of MIDI note numbers supported by your music box,
+      or song to make a custom tape with a row for each note the song plays. Overrides --box.
+    --transpose <shift>  Semitones to transpose by, or auto to pick the shift that leaves the
+      fewest notes unplayable. (default: 0)
+    --transpose-range <semitones>  Largest shift up or down tried by --transpose auto.
+      [default: 12]
+    --missing-notes <policy>  What to do with notes not in --notes: fail, drop, fold (move by
+      octaves), or nearest (closest available pitch). (default: fail)
+    --max-polyphony <num>  Most notes to play at once. Extra notes in a chord are dropped.
+    --chord-strategy <strategy>  Which notes of a chord to keep: highest, lowest, or melody
+      (highest and lowest first). (default: melody)
+    --chord-window <width>  Notes closer together than this are played as one chord. (default: 0)
+    --tape-height <height>  Height of programming tape. Overrides --box.
+    --space-above-top-row <space>  Space between edge of tape and first row. Overrides --box.
+    --space-below-bottom-row <space>  Space between last row and edge of tape. Overrides --box.
+    --space-before-first-note <space>  Space between end of lead-in and first note. (default: 20)
+    --space-after-last-note <space>  Space between last note and end of tape. (default: 20)
+    --space-between-strips <space>  Vertical space between two strips cut from the same page.
+      (default: 10)
+    --hole-diameter <diameter>  Diameter of each hole. Overrides --box.
+    --min-hole-distance <distance>  Warn about holes in the same row closer than this (center to
+      center). Holes closer than their diameter always get a warning. (default: 0)
+    --strict  Fail instead of warning about holes that are too close together.
+    --highlight-collisions  Mark holes that are too close together in SVG output.
+    --page-width <width>  Width of the page. (default: 297)
+    --page-height <height>  Height of the page. (default: 210)
+    --margin-left <margin>  Left margin. (default: 10)
+    --margin-right <margin>  Right margin. (default: 10)
+    --margin-top <margin>  Top margin. (default: 10)
+    --margin-bottom <margin>  Bottom margin. (default: 10)
+    --cut-stroke-width <width>  Width of lines to be cut. Should equal the kerf. (default: 0.08)
+    --cut-color <color>  SVG color of lines to be cut. (default: red)
+    --engrave-color <color>  SVG color for engraving. (default: black)
+    --highlight-color <color>  SVG color of the marks made by --highlight-collisions. Must differ
+      from the cut and engrave colors. (default: orange)
+    --grid  Engrave lines along each row and across the tape at each beat and bar.
+    --note-labels  Engrave note names (e.g. C4, F#5) next to the rows before the first note, on
+      the first strip only. Needs enough --space-before-first-note for the names.
+    --hole-labels <style>  Engrave the note of each hole next to it: pitch (e.g. C4) or solfege
+      (e.g. Do).
+    --stretch <factor>  Horizontal stretch factor (mm / beat, or mm / second for SMPTE
+      time-code files). Overrides --box.
+    --start <position>  Only lay out notes from this point on, given in bars, beats (quarter
+      notes) or ticks, e.g. 17bar, 65beat or 30720tick.
+    --end <position>  Only lay out notes before this point, e.g. 25bar to stop after bar 24.
+    --quantize <grid>  Snap notes to a grid given as a note value (e.g. 1/16) or in ticks.
+    --swing <amount>  Delay every other grid position by this fraction of the grid. (default: 0)
+    --tempo-map  Space notes by real time, following tempo changes (stretch is then mm / second).
+    --lead-in-width <width>  Width of diagonal edge at beginning of first page. (default: 15)
+    --lead-in-height <width>  Height of diagonal edge at beginning of first page. (default: 35)
+    --num-zig-zags <num>  Number of zig-zags in connecting edges. (default: 5)
+    --join-width <width>  Width of connecting edge join. (default: 5)
+    --join-style <style>  Straight, zigzag, or diagonal. (default: zigzag)
+    --loop <unit>  Make a closed loop tape (joined at both ends) padded to a whole number of
+      beats or bars.
+    --title <title>  Name of song.
+    --font-file <ttf>  Font to use for PDF format.
+    --output-format <format>  SVG, PDF, DXF, or JSON.
+";
+
+/// The flags in `FLAGS_USAGE`, as parsed by docopt. Every front end turns them into `Options`
+/// the same way with `Flags::options`.
+#[derive(Debug, Deserialize)]
+pub struct Flags {
+    pub flag_config: Option<String>,
+    pub flag_tracks: Option<String>,
+    pub flag_track_num: Option<String>,
+    pub flag_channels: Option<String>,
+    pub flag_min_velocity: Option<u8>,
+    pub flag_box: Option<String>,
+    pub flag_box_file: Option<String>,
+    pub flag_notes: Option<String>,
+    pub flag_transpose: Option<String>,
+    pub flag_transpose_range: i8,
+    pub flag_missing_notes: Option<MissingNotePolicy>,
+    pub flag_max_polyphony: Option<usize>,
+    pub flag_chord_strategy: Option<ChordStrategy>,
+    pub flag_chord_window: Option<f64>,
+    pub flag_tape_height: Option<f64>,
+    pub flag_space_above_top_row: Option<f64>,
+    pub flag_space_below_bottom_row: Option<f64>,
+    pub flag_space_before_first_note: Option<f64>,
+    pub flag_space_after_last_note: Option<f64>,
+    pub flag_space_between_strips: Option<f64>,
+    pub flag_page_width: Option<f64>,
+    pub flag_page_height: Option<f64>,
+    pub flag_margin_left: Option<f64>,
+    pub flag_margin_top: Option<f64>,
+    pub flag_margin_right: Option<f64>,
+    pub flag_margin_bottom: Option<f64>,
+    pub flag_hole_diameter: Option<f64>,
+    pub flag_min_hole_distance: Option<f64>,
+    pub flag_strict: bool,
+    pub flag_highlight_collisions: bool,
+    pub flag_cut_stroke_width: Option<f64>,
+    pub flag_cut_color: Option<String>,
+    pub flag_engrave_color: Option<String>,
+    pub flag_highlight_color: Option<String>,
+    pub flag_grid: bool,
+    pub flag_note_labels: bool,
+    pub flag_hole_labels: Option<LabelStyle>,
+    pub flag_stretch: Option<f64>,
+    pub flag_start: Option<String>,
+    pub flag_end: Option<String>,
+    pub flag_quantize: Option<String>,
+    pub flag_swing: Option<f64>,
+    pub flag_tempo_map: bool,
+    pub flag_lead_in_width: Option<f64>,
+    pub flag_lead_in_height: Option<f64>,
+    pub flag_num_zig_zags: Option<u16>,
+    pub flag_join_width: Option<f64>,
+    pub flag_join_style: Option<JoinStyle>,
+    pub flag_loop: Option<LoopPadding>,
+    pub flag_title: Option<String>,
+    pub flag_font_file: Option<String>,
+    pub flag_output_format: Option<String>,
+}
+
+impl Flags {
+    /// Builds options from the `--config` file (or the defaults), then the `--box` preset, then
+    /// every other flag that was given. With `--transpose auto`, `transpose` is left as it was;
+    /// see `auto_transpose_range`.
+    pub fn options(&self) -> Result<Options, Error> {
+        let mut options = match self.flag_config {
+            Some(ref path) => Options::load(Path::new(path))?,
+            None => Options::default(),
+        };
+        if let Some(ref name) = self.flag_box {
+            let mut presets = box_presets();
+            if let Some(ref path) = self.flag_box_file {
+                presets.extend(load_box_presets(Path::new(path))?);
+            }
+            // Presets loaded from a file replace built-in presets of the same name.
+            let preset = presets
+                .into_iter()
+                .rev()
+                .find(|p| p.name.eq_ignore_ascii_case(name))
+                .ok_or_else(|| Error::InvalidFlag(format!("unknown music box: {}", name)))?;
+            options.apply_box_preset(&preset);
+        }
+        if self.flag_notes.is_some() || self.flag_tape_height.is_some() ||
+            self.flag_space_above_top_row.is_some() ||
+            self.flag_space_below_bottom_row.is_some()
+        {
+            // Keep whichever of the dimensions were not given and space the rows evenly again.
+            let space_below_bottom_row = self.flag_space_below_bottom_row.unwrap_or(
+                options.tape_height - options.interior_margin_top -
+                    options.row_spacing * (options.notes.len() as f64 - 1.0),
+            );
+            match self.flag_notes {
+                Some(_) if self.notes_from_song() => {}
+                Some(ref list) => {
+                    options.notes = list.split(',')
+                        .map(|x| parse_flag(x, "note number"))
+                        .collect::<Result<_, _>>()?;
+                }
+                None => {}
+            }
+            options.tape_height = self.flag_tape_height.unwrap_or(options.tape_height);
+            options.interior_margin_top = self.flag_space_above_top_row.unwrap_or(
+                options.interior_margin_top,
+            );
+            // A single row has no spacing to adjust.
+            if options.notes.len() > 1 {
+                options.row_spacing = (options.tape_height - options.interior_margin_top -
+                                           space_below_bottom_row) /
+                    (options.notes.len() as f64 - 1.0);
+            }
+        }
+        if let Some(list) = self.flag_tracks.as_ref().or(self.flag_track_num.as_ref()) {
+            options.tracks = parse_list_or_all(list, "track number")?;
+        }
+        if let Some(ref list) = self.flag_channels {
+            options.channels = parse_list_or_all(list, "channel number")?;
+        }
+        if self.flag_transpose_range < 0 {
+            return Err(Error::InvalidFlag(format!(
+                "transpose range must be from 0 to 127: {}",
+                self.flag_transpose_range
+            )));
+        }
+        match self.flag_transpose {
+            Some(ref shift) if shift.eq_ignore_ascii_case("auto") => {}
+            Some(ref shift) => options.transpose = parse_flag(shift, "transposition")?,
+            None => {}
+        }
+        options.min_velocity = self.flag_min_velocity.unwrap_or(options.min_velocity);
+        options.missing_notes = self.flag_missing_notes.unwrap_or(options.missing_notes);
+        options.max_polyphony = self.flag_max_polyphony.or(options.max_polyphony);
+        options.chord_strategy = self.flag_chord_strategy.unwrap_or(options.chord_strategy);
+        options.chord_window = self.flag_chord_window.unwrap_or(options.chord_window);
+        options.min_hole_distance = self.flag_min_hole_distance.unwrap_or(
+            options.min_hole_distance,
+        );
+        options.strict |= self.flag_strict;
+        options.highlight_collisions |= self.flag_highlight_collisions;
+        options.interior_margin_left = self.flag_space_before_first_note.unwrap_or(
+            options.interior_margin_left,
+        );
+        options.interior_margin_right = self.flag_space_after_last_note.unwrap_or(
+            options.interior_margin_right,
+        );
+        options.gap = self.flag_space_between_strips.unwrap_or(options.gap);
+        options.page_width = self.flag_page_width.unwrap_or(options.page_width);
+        options.page_height = self.flag_page_height.unwrap_or(options.page_height);
+        options.margin_left = self.flag_margin_left.unwrap_or(options.margin_left);
+        options.margin_top = self.flag_margin_top.unwrap_or(options.margin_top);
+        options.margin_right = self.flag_margin_right.unwrap_or(options.margin_right);
+        options.margin_bottom = self.flag_margin_bottom.unwrap_or(options.margin_bottom);
+        if let Some(diameter) = self.flag_hole_diameter {
+            options.hole_radius = diameter / 2.0;
+        }
+        options.cut_stroke_width = self.flag_cut_stroke_width.unwrap_or(
+            options.cut_stroke_width,
+        );
+        if let Some(ref color) = self.flag_cut_color {
+            options.cut_color = parse_flag(color, "cut color")?;
+        }
+        if let Some(ref color) = self.flag_engrave_color {
+            options.engrave_color = parse_flag(color, "engrave color")?;
+        }
+        if let Some(ref color) = self.flag_highlight_color {
+            options.highlight_color = parse_flag(color, "highlight color")?;
+        }
+        options.draw_grid |= self.flag_grid;
+        options.note_labels |= self.flag_note_labels;
+        options.hole_labels = self.flag_hole_labels.or(options.hole_labels);
+        options.stretch = self.flag_stretch.unwrap_or(options.stretch);
+        if let Some(ref p) = self.flag_start {
+            options.start = Some(parse_flag(p, "start position")?);
+        }
+        if let Some(ref p) = self.flag_end {
+            options.end = Some(parse_flag(p, "end position")?);
+        }
+        if let Some(ref q) = self.flag_quantize {
+            options.quantize = Some(parse_flag(q, "quantization grid")?);
+        }
+        options.swing = self.flag_swing.unwrap_or(options.swing);
+        options.tempo_map |= self.flag_tempo_map;
+        options.lead_in_width = self.flag_lead_in_width.unwrap_or(options.lead_in_width);
+        options.lead_in_height = self.flag_lead_in_height.unwrap_or(options.lead_in_height);
+        options.num_zig_zags = self.flag_num_zig_zags.unwrap_or(options.num_zig_zags);
+        options.join_width = self.flag_join_width.unwrap_or(options.join_width);
+        options.join_style = self.flag_join_style.unwrap_or(options.join_style);
+        options.loop_padding = self.flag_loop.or(options.loop_padding);
+        if let Some(ref title) = self.flag_title {
+            options.title = title.clone();
+        }
+        if let Some(ref font_file) = self.flag_font_file {
+            options.font_file = Some(font_file.clone());
+        }
+        Ok(options)
+    }
+
+    /// Builds and validates the options like `Flags::options`, then settles `--transpose auto`
+    /// and `--notes song` against the song. Also returns every transposition that was tried,
+    /// which is empty unless `--transpose auto` was given.
+    pub fn resolve(&self, smf: Option<&SMF>) -> Result<(Options, Vec<Transposition>), Error> {
+        let mut options = self.options()?;
+        options.validate()?;
+        let mut transpositions = Vec::new();
+        if let Some(range) = self.auto_transpose_range() {
+            let smf = smf.ok_or_else(|| {
+                Error::InvalidFlag("--transpose auto needs a MIDI file".to_string())
+            })?;
+            transpositions = options.transpositions(smf, -range, range)?;
+            options.transpose = best_transposition(&transpositions)
+                .ok_or_else(|| Error::InvalidFlag(format!("transpose range: {}", range)))?
+                .shift;
+        }
+        if self.notes_from_song() {
+            let smf = smf.ok_or_else(|| {
+                Error::InvalidFlag("--notes song needs a MIDI file".to_string())
+            })?;
+            options.fit_notes_to_song(smf)?;
+        }
+        Ok((options, transpositions))
+    }
+
+    /// Returns the largest shift to try if `--transpose auto` was given.
+    pub fn auto_transpose_range(&self) -> Option<i8> {
+        match self.flag_transpose {
+            Some(ref shift) if shift.eq_ignore_ascii_case("auto") => Some(self.flag_transpose_range),
+            _ => None,
+        }
+    }
+
+    /// Returns whether `--notes song` was given, in which case `notes` is left as it was; see
+    /// `Options::fit_notes_to_song`.
+    pub fn notes_from_song(&self) -> bool {
+        self.flag_notes.as_ref().map_or(false, |n| n.eq_ignore_ascii_case("song"))
+    }
+
+    /// Picks the format named by `--output-format`, or else the one whose extension matches the
+    /// output file name, or else `default`.
+    pub fn output_format(
+        &self,
+        output: Option<&str>,
+        default: &str,
+    ) -> Result<OutputFormat, Error> {
+        let formats = output_formats();
+        let format = match self.flag_output_format {
+            Some(ref name) => formats.iter().find(|f| f.name.eq_ignore_ascii_case(name)),
+            None => {
+                output
+                    .map(|o| o.to_lowercase())
+                    .and_then(|o| {
+                        formats.iter().find(
+                            |f| o.ends_with(&format!(".{}", f.extension)),
+                        )
+                    })
+                    .or_else(|| formats.iter().find(|f| f.name == default))
+            }
+        };
+        format.cloned().ok_or_else(|| {
+            Error::InvalidFlag(format!(
+                "unknown output format: {}",
+                self.flag_output_format.as_ref().map_or(default, |n| &n[..])
+            ))
+        })
+    }
+}
+
+fn parse_flag<T: FromStr>(value: &str, what: &str) -> Result<T, Error> {
+    value.parse().map_err(
+        |_| Error::InvalidFlag(format!("{}: {}", what, value)),
+    )
+}
+
+fn parse_list_or_all<T: FromStr>(list: &str, what: &str) -> Result<Option<Vec<T>>, Error> {
+    if list.eq_ignore_ascii_case("all") {
+        Ok(None)
+    } else {
+        list.split(',').map(|x| parse_flag(x, what)).collect::<Result<_, _>>().map(Some)
+    }
+}
+
+/// Picks the transposition that leaves the fewest notes unplayable, preferring smaller shifts.
+pub fn best_transposition(transpositions: &[Transposition]) -> Option<Transposition> {
+    transpositions
+        .iter()
+        .min_by_key(|t| (t.unplayable, (t.shift as i16).abs(), t.shift))
+        .cloned()
+}
+
+#[derive(Debug)]
 pub enum Error {
-    /// Time-code based MIDI files are not supported.
+    /// The file's time division is malformed or uses an unknown SMPTE frame rate.
     UnsupportedDiv,
-    /// The requested track does not exist in the file.
+    /// A requested track does not exist in the file.
     TrackNotFound,
-    /// The requested track has zero notes in it.
+    /// The requested tracks and channels have zero playable notes in them.
     EmptyTrack,
     /// A note was present in the track that does not appear in the notes list.
     InvalidNote(u8),
+    /// Strict mode was requested and this many pairs of holes are too close together.
+    HoleCollisions(usize),
+    /// Reading or writing a file failed.
+    Io(io::Error),
+    /// The font file for engraved text could not be read.
+    Font(String),
+    /// The PDF document could not be generated.
+    Pdf(String),
+    /// The DXF drawing could not be written.
+    Dxf(String),
+    /// A file of box presets could not be parsed.
+    InvalidPresetFile(String),
+    /// A file of options could not be parsed.
+    InvalidConfigFile(String),
+    /// A command line flag has a value that could not be parsed.
+    InvalidFlag(String),
+    /// The options describe a tape that cannot be laid out, for each of these reasons.
+    InvalidOptions(Vec<String>),
+    /// A `Renderer` method was called out of order, e.g. drawing before `begin_page`.
+    OutOfOrder(&'static str),
 }
 
-impl Options {
-    pub fn layout(&self, smf: SMF) -> Result<Vec<Page>, Error> {
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use Error::*;
-        let div = smf.division;
-        if div <= 0 {
-            return Err(UnsupportedDiv);
-        } else if smf.tracks.len() <= self.track_num {
-            return Err(TrackNotFound);
-        }
-
-        let mut time = 0;
-        let mut max = 0;
-        let notes = {
-            let mut notes = Vec::new();
-            for event in &smf.tracks[self.track_num].events {
-                time += event.vtime;
-                match event.event {
-                    Event::Midi(ref msg) => {
-                        if msg.status() == Status::NoteOn {
-                            if time > max {
-                                max = time;
-                            }
-                            notes.push(Note {
-                                time: time,
-                                note: 128 - msg.data(1),
-                            });
-                        }
-                    }
-                    Event::Meta(_) => {}
-                };
+        match *self {
+            UnsupportedDiv => write!(f, "unsupported time division"),
+            TrackNotFound => write!(f, "track not found"),
+            EmptyTrack => write!(f, "no playable notes in the selected tracks and channels"),
+            InvalidNote(note) => {
+                write!(
+                    f,
+                    "note {} ({}) is not in the notes list",
+                    note,
+                    note_name(note)
+                )
             }
-            notes.sort_unstable();
-            let min_time = notes[0].time;
-            for note in notes.iter_mut() {
-                note.time -= min_time;
+            HoleCollisions(count) => write!(f, "{} pairs of holes are too close together", count),
+            Io(ref e) => write!(f, "{}", e),
+            Font(ref e) => write!(f, "failed to load font: {}", e),
+            Pdf(ref e) => write!(f, "failed to generate PDF: {}", e),
+            Dxf(ref e) => write!(f, "failed to write DXF: {}", e),
+            InvalidPresetFile(ref e) => write!(f, "invalid box preset file: {}", e),
+            InvalidConfigFile(ref e) => write!(f, "invalid config file: {}", e),
+            InvalidFlag(ref e) => write!(f, "invalid argument: {}", e),
+            InvalidOptions(ref problems) => {
+                write!(f, "invalid options:")?;
+                for problem in problems {
+                    write!(f, "\n  - {}", problem)?;
+                }
+                Ok(())
             }
-            notes
-        };
-        if notes.is_empty() {
-            return Err(EmptyTrack);
+            OutOfOrder(e) => write!(f, "renderer called out of order: {}", e),
         }
-        let join_width = if self.join_style == JoinStyle::Straight {
-            0.0
-        } else {
-            self.join_width
-        };
-        let max_time = notes[notes.len() - 1].time;
-        let total_width = self.time_to_width(div, max_time);
-        let usable_width_first_strip =
-            self.page_width - self.margin_left - self.margin_right - self.lead_in_width -
-                self.interior_margin_left - self.hole_radius - join_width;
-        let usable_width_middle_strip = self.page_width - self.margin_left - self.margin_right -
-            join_width;
-        let usable_width_last_strip = self.page_width - self.margin_left - self.margin_right -
-            self.interior_margin_right - self.hole_radius;
-        let usable_width_only_strip = self.page_width - self.margin_left - self.margin_right -
-            self.lead_in_width - self.interior_margin_left -
-            self.interior_margin_right -
-            (2.0 * self.hole_radius);
-        let num_strips = if total_width <= usable_width_only_strip {
-            1
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl Options {
+    /// Reads options from a JSON file if its name ends in `.json`, or a TOML file otherwise.
+    pub fn load(path: &Path) -> Result<Options, Error> {
+        let mut contents = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut contents))
+            .map_err(|e| Error::InvalidConfigFile(format!("{}: {}", path.display(), e)))?;
+        let is_json = path.extension().map_or(
+            false,
+            |e| e.eq_ignore_ascii_case("json"),
+        );
+        let options = if is_json {
+            serde_json::from_str(&contents).map_err(|e| e.to_string())
         } else {
-            2 +
-                ((total_width - usable_width_first_strip - usable_width_last_strip) /
-                     usable_width_middle_strip)
-                    .ceil() as u16
+            toml::from_str(&contents).map_err(|e| e.to_string())
         };
-        let strips_per_page = 1 +
-            ((self.page_height - self.margin_top - self.margin_bottom - self.tape_height) /
-                 (self.gap + self.tape_height))
-                .floor() as u16;
-        let num_pages = (num_strips + strips_per_page - 1) / strips_per_page;
+        options.map_err(|e| Error::InvalidConfigFile(format!("{}: {}", path.display(), e)))
+    }
+
+    /// Takes the notes and tape dimensions from a music box preset.
+    pub fn apply_box_preset(&mut self, preset: &BoxPreset) {
+        self.notes = preset.notes.clone();
+        self.tape_height = preset.tape_height;
+        self.interior_margin_top = preset.space_above_top_row;
+        if preset.notes.len() > 1 {
+            self.row_spacing = (preset.tape_height - preset.space_above_top_row -
+                                    preset.space_below_bottom_row) /
+                (preset.notes.len() as f64 - 1.0);
+        }
+        self.hole_radius = preset.hole_diameter / 2.0;
+        self.stretch = preset.stretch;
+    }
+
+    pub fn layout(&self, smf: SMF) -> Result<Vec<Page>, Error> {
+        self.layout_with_report(smf).map(|(pages, _)| pages)
+    }
+
+    /// Like `layout`, but also reports every change made to the song along the way.
+    pub fn layout_with_report(&self, smf: SMF) -> Result<(Vec<Page>, Report), Error> {
+        self.lay_out(&smf)
+    }
+
+    /// Writes each page as SVG to a stream from `make_output_stream`. Same as `render` with an
+    /// `SvgRenderer`.
+    pub fn make_svg(
+        &self,
+        pages: &[Page],
+        make_output_stream: &mut dyn FnMut(usize) -> Box<dyn Write>,
+    ) -> io::Result<()> {
+        let mut make_output_stream = |page_num| Ok(make_output_stream(page_num));
+        render(pages, &mut SvgRenderer::new(self, &mut make_output_stream)).map_err(io_error)
+    }
+
+    /// Writes all pages as one PDF document. Same as `render` with a `PdfRenderer`.
+    #[cfg(feature = "pdf")]
+    pub fn make_pdf(&self, pages: &[Page], output: &mut dyn Write) -> io::Result<()> {
+        // The renderer wants to own its stream, so let it write to a buffer shared with this.
+        let buffer = SharedBuffer::default();
+        {
+            let mut make_output_stream =
+                |_| Ok(Box::new(buffer.clone()) as Box<dyn Write>);
+            render(pages, &mut PdfRenderer::new(self, &mut make_output_stream))
+                .map_err(io_error)?;
+        }
+        let bytes = buffer.0.borrow();
+        output.write_all(&bytes)
+    }
+
+    /// Writes each page as DXF to a stream from `make_output_stream`. Same as `render` with a
+    /// `DxfRenderer`.
+    #[cfg(feature = "dxf")]
+    pub fn make_dxf(
+        &self,
+        pages: &[Page],
+        make_output_stream: &mut dyn FnMut(usize) -> Box<dyn Write>,
+    ) -> io::Result<()> {
+        let mut make_output_stream = |page_num| Ok(make_output_stream(page_num));
+        render(pages, &mut DxfRenderer::new(self, &mut make_output_stream)).map_err(io_error)
+    }
+
+    fn note_label_font_size(&self) -> f64 {
+        // Leave a little space between the labels of neighboring rows.
+        self.row_spacing * 0.9
+    }
+
+    /// A generous estimate of the width of the longest note label.
+    fn note_label_width(&self) -> f64 {
+        // Letters, digits and '#' are at most about 0.6em wide in the fonts used for engraving.
+        let chars = self.notes.iter().map(|&note| note_name(note).len()).max().unwrap_or(0);
+        chars as f64 * 0.6 * self.note_label_font_size()
+    }
+
+    fn lay_out(&self, smf: &SMF) -> Result<(Vec<Page>, Report), Error> {
+        use Error::*;
+        self.validate()?;
+        let mut report = Report::default();
+        let Song {
+            div,
+            notes,
+            end,
+            beats,
+        } = self.read_notes(smf)?;
+        let notes = self.playable_notes(notes, &mut report)?;
+        let notes = self.thin_chords(div, notes, &mut report)?;
+        if notes.is_empty() {
+            return Err(EmptyTrack);
+        }
+        let join_width = self.effective_join_width();
+        let looped = self.loop_padding.is_some();
+        // A loop ends exactly where it starts again.
+        let max_time = if looped {
+            end
+        } else {
+            notes[notes.len() - 1].time.max(end)
+        };
+        let total_width = self.time_to_width(div, max_time);
+        let UsableWidths {
+            first_strip: usable_width_first_strip,
+            middle_strip: usable_width_middle_strip,
+            ..
+        } = self.usable_widths();
+        // Loops have joins at both ends of every strip, so every strip is a middle strip. The
+        // first note is placed just after the first strip's join.
+        let loop_lead = join_width + self.hole_radius;
+        let (num_strips, strips_per_page) = self.strip_counts(total_width);
+        let num_pages = (num_strips + strips_per_page - 1) / strips_per_page;
         let mut pages = Vec::new();
         for page_num in 0..num_pages {
             let mut strips = Vec::new();
@@ -192,7 +1463,9 @@ impl Options {
                 }
                 let first_strip = strip_num == 0;
                 let last_strip = strip_num + 1 == num_strips;
-                let x_offset = if first_strip {
+                let x_offset = if looped {
+                    loop_lead - strip_num as f64 * usable_width_middle_strip
+                } else if first_strip {
                     self.lead_in_width + self.interior_margin_left + self.hole_radius
                 } else {
                     -(usable_width_first_strip +
@@ -202,15 +1475,23 @@ impl Options {
                     strip_on_page as f64 * (self.tape_height + self.gap);
                 let bottom_edge = top_edge + self.tape_height;
                 let left_edge = self.margin_left;
-                let right_edge = if last_strip {
-                    self.time_to_width(div, notes.last().unwrap().time) + x_offset +
+                let right_edge = if looped && last_strip {
+                    left_edge + total_width - strip_num as f64 * usable_width_middle_strip
+                } else if last_strip {
+                    self.time_to_width(div, max_time) + x_offset +
                         self.interior_margin_right + self.hole_radius
                 } else {
                     self.page_width - self.margin_right - join_width
                 };
+                // The right end of the tape, including any join.
+                let right_limit = if last_strip && !looped {
+                    right_edge
+                } else {
+                    right_edge + join_width
+                };
                 let outline = {
                     let mut points = Vec::new();
-                    if first_strip {
+                    if first_strip && !looped {
                         let lead_in_right = left_edge + self.lead_in_width;
                         let lead_in_top = top_edge + (self.tape_height - self.lead_in_height);
                         points.push((left_edge, top_edge));
@@ -219,7 +1500,7 @@ impl Options {
                     } else {
                         points.extend(self.join(left_edge, top_edge));
                     }
-                    if last_strip {
+                    if last_strip && !looped {
                         points.push((right_edge, bottom_edge));
                         points.push((right_edge, top_edge));
                     } else {
@@ -227,11 +1508,11 @@ impl Options {
                     }
                     points
                 };
-                let texts = if self.title.is_empty() {
+                let mut texts = if self.title.is_empty() {
                     Vec::new()
                 } else {
                     let x = left_edge +
-                        if first_strip {
+                        if first_strip && !looped {
                             self.lead_in_width
                         } else {
                             join_width + 1.0
@@ -245,233 +1526,781 @@ impl Options {
                         },
                     ]
                 };
+                // Only the first strip has room for labels before its first note.
+                if self.note_labels && first_strip && !looped {
+                    let x = left_edge + self.lead_in_width;
+                    let font_size = self.note_label_font_size();
+                    for (row, &note) in self.notes.iter().enumerate() {
+                        let y = top_edge + self.interior_margin_top + row as f64 * self.row_spacing;
+                        texts.push(Text {
+                            // Text is positioned by its baseline, so move it down to center it
+                            // on the row.
+                            position: (x, y + font_size / 3.0),
+                            text: note_name(note),
+                            font_size,
+                        });
+                    }
+                }
                 let mut holes = Vec::new();
-                for note in &notes {
-                    let row = match self.notes.iter().position(|&n| n == note.note) {
-                        Some(i) => i,
-                        None => {
-                            return Err(InvalidNote(note.note));
+                // Notes near the seam of a loop also appear on the other side of it.
+                let wraps = if looped {
+                    vec![-total_width, 0.0, total_width]
+                } else {
+                    vec![0.0]
+                };
+                for &wrap in &wraps {
+                    for note in &notes {
+                        let row = match self.notes.iter().position(|&n| n == note.note) {
+                            Some(i) => i,
+                            None => {
+                                return Err(InvalidNote(note.note));
+                            }
+                        };
+                        let offset = self.time_to_width(div, note.time);
+                        let x = offset + x_offset + wrap;
+                        // Skip holes that would lie entirely off the ends of the strip.
+                        if x + self.hole_radius <= 0.0 {
+                            // TODO: Binary search instead
+                            continue;
+                        } else if x + left_edge - self.hole_radius >= right_limit {
+                            break;
+                        }
+                        let y = row as f64 * self.row_spacing + self.interior_margin_top;
+                        holes.push(Hole {
+                            position: (x + left_edge, y + top_edge),
+                            note: note.note,
+                            row,
+                            tick: note.tick,
+                            strip: strip_num as usize,
+                            offset,
+                        });
+                    }
+                }
+                if let Some(style) = self.hole_labels {
+                    let font_size = self.hole_radius;
+                    for hole in &holes {
+                        let (x, y) = hole.position;
+                        texts.push(Text {
+                            position: (x + self.hole_radius * 1.5, y + font_size / 3.0),
+                            text: style.label(hole.note),
+                            font_size,
+                        });
+                    }
+                }
+                let mut grid = Vec::new();
+                if self.draw_grid {
+                    // Keep the lines clear of the lead-in and joins.
+                    let inner_left = left_edge +
+                        if first_strip && !looped {
+                            self.lead_in_width
+                        } else {
+                            join_width
+                        };
+                    for row in 0..self.notes.len() {
+                        let y = top_edge + self.interior_margin_top + row as f64 * self.row_spacing;
+                        grid.push(GridLine {
+                            start: (inner_left, y),
+                            end: (right_edge, y),
+                            bar: false,
+                        });
+                    }
+                    for &wrap in &wraps {
+                        for &(time, bar) in &beats {
+                            if looped && time >= max_time {
+                                // The end of a loop is drawn at its start.
+                                break;
+                            }
+                            let x = self.time_to_width(div, time) + x_offset + wrap + left_edge;
+                            if x < inner_left {
+                                continue;
+                            } else if x > right_edge {
+                                break;
+                            }
+                            grid.push(GridLine {
+                                start: (x, top_edge),
+                                end: (x, bottom_edge),
+                                bar,
+                            });
                         }
-                    };
-                    let x = self.time_to_width(div, note.time) + x_offset;
-                    if x + self.hole_radius < 0.0 {
-                        // TODO: Binary search instead
-                        continue;
-                    } else if x + left_edge - self.hole_radius >
-                               self.page_width - self.margin_right
-                    {
-                        break;
                     }
-                    let y = row as f64 * self.row_spacing + self.interior_margin_top;
-                    holes.push((x + left_edge, y + top_edge));
                 }
                 strips.push(Strip {
-                    texts: texts,
-                    outline: outline,
-                    holes: holes,
+                    texts,
+                    outline,
+                    holes,
+                    grid,
                 });
             }
-            pages.push(Page { strips: strips });
+            pages.push(Page { strips });
         }
-        Ok(pages)
+        report.collisions = self.find_collisions(&pages);
+        if self.strict && !report.collisions.is_empty() {
+            return Err(HoleCollisions(report.collisions.len()));
+        }
+        Ok((pages, report))
     }
 
-    pub fn make_svg(
-        &self,
-        pages: &[Page],
-        make_output_stream: &mut FnMut(usize) -> Box<Write>,
-    ) -> io::Result<()> {
-        let mut strip_num = 0;
-        for (page_num, page) in pages.iter().enumerate() {
-            let mut output = make_output_stream(page_num);
-            writeln!(output, r#"<?xml version="1.0" encoding="UTF-8" ?>"#)?;
-            writeln!(
-                output,
-                r#"<svg xmlns="http://www.w3.org/2000/svg" version="1.1" width="{page_width:.2}mm" height="{page_height:.2}mm" viewBox="0 0 {page_width:.2} {page_height:.2}">"#,
-                page_width = self.page_width,
-                page_height = self.page_height
-            )?;
-            writeln!(
-                output,
-                r#"<g fill="none" stroke-width="{cut_stroke_width:.2}" stroke="rgba({r},{g},{b},{a:.2})">"#,
-                cut_stroke_width = self.cut_stroke_width,
-                r = self.cut_color.r,
-                g = self.cut_color.g,
-                b = self.cut_color.b,
-                a = self.cut_color.a,
-            )?;
-            for strip in &page.strips {
-                writeln!(
-                    output,
-                    r#"<defs><clipPath id="strip_{}_border">"#,
-                    strip_num
-                )?;
-                self.polygon(&strip.outline[..], &mut output);
-                writeln!(output, "</clipPath></defs>")?;
-                writeln!(
-                    output,
-                    r#"<g clip-path="url(#strip_{}_border)">"#,
-                    strip_num
-                )?;
-
-                // Draw the border with double the stroke width then clip the half of that inside
-                // the border producing exactly the requested strip size (assuming that stroke
-                // width is exactly equal to the kerf.
-                writeln!(
-                    output,
-                    r#"<g stroke-width="{:.2}">"#,
-                    self.cut_stroke_width * 2.0
-                )?;
-                self.polygon(&strip.outline[..], &mut output);
-                writeln!(output, "</g>")?;
-                for text in &strip.texts {
-                    writeln!(
-                        output,
-                        r#"<text x="{x:.2}" y="{y:.2}" font-size="{font_size:.2}" fill="rgba({r},{g},{b},{a:.2})" stroke="none">{text}</text>"#,
-                        x = text.position.0,
-                        y = text.position.1,
-                        font_size = text.font_size,
-                        text = text.text,
-                        r = self.engrave_color.r,
-                        g = self.engrave_color.g,
-                        b = self.engrave_color.b,
-                        a = self.engrave_color.a,
-                    )?;
+    /// Checks that the options describe a tape that can be laid out, and explains every problem
+    /// found with `Error::InvalidOptions`.
+    pub fn validate(&self) -> Result<(), Error> {
+        let mut problems = Vec::new();
+        {
+            let mut check = |ok: bool, problem: String| if !ok {
+                problems.push(problem);
+            };
+            // Written so that NaN fails every check.
+            let positive = |value: f64| value > 0.0;
+            let non_negative = |value: f64| value >= 0.0;
+            for &(name, value) in &[
+                ("tape height", self.tape_height),
+                ("page width", self.page_width),
+                ("page height", self.page_height),
+                ("hole radius", self.hole_radius),
+                ("stretch", self.stretch),
+            ]
+            {
+                check(
+                    positive(value),
+                    format!("{} must be positive, not {}", name, value),
+                );
+            }
+            for &(name, value) in &[
+                ("space above the top row", self.interior_margin_top),
+                ("space before the first note", self.interior_margin_left),
+                ("space after the last note", self.interior_margin_right),
+                ("left margin", self.margin_left),
+                ("top margin", self.margin_top),
+                ("right margin", self.margin_right),
+                ("bottom margin", self.margin_bottom),
+                ("space between strips", self.gap),
+                ("cut stroke width", self.cut_stroke_width),
+                ("lead-in width", self.lead_in_width),
+                ("lead-in height", self.lead_in_height),
+                ("join width", self.join_width),
+                ("chord window", self.chord_window),
+                ("minimum hole distance", self.min_hole_distance),
+            ]
+            {
+                check(
+                    non_negative(value),
+                    format!("{} must not be negative, not {}", name, value),
+                );
+            }
+            check(!self.notes.is_empty(), "the notes list is empty".to_string());
+            for &note in &self.notes {
+                check(
+                    (1..=128).contains(&note),
+                    format!("note {} is outside the MIDI range (1-128)", note),
+                );
+            }
+            for &(name, position) in &[("start", self.start), ("end", self.end)] {
+                check(
+                    position != Some(Position::Bar(0)) && position != Some(Position::Beat(0)),
+                    format!("the {} position must count bars and beats from 1", name),
+                );
+            }
+            if let Some(ref channels) = self.channels {
+                for &channel in channels {
+                    check(
+                        (1..=16).contains(&channel),
+                        format!("channel {} does not exist (channels are 1-16)", channel),
+                    );
                 }
-                for hole in &strip.holes {
-                    writeln!(
-                        output,
-                        r#"<circle cx="{x:.2}" cy="{y:.2}" r="{hole_radius:.2}" />"#,
-                        x = hole.0,
-                        y = hole.1,
-                        // Reduce the radius by 1/2 the kerf to create a resulting hole of the exact
-                        // size requested.
-                        hole_radius = self.hole_radius - (self.cut_stroke_width / 2.0),
-                    )?;
+            }
+            check(
+                self.max_polyphony != Some(0),
+                "the polyphony limit must be at least 1".to_string(),
+            );
+            check(
+                self.swing >= 0.0 && self.swing < 1.0,
+                format!("swing must be at least 0 and less than 1, not {}", self.swing),
+            );
+            check(
+                self.join_style != JoinStyle::ZigZag || self.num_zig_zags > 0,
+                "zig-zag joins need at least one zig-zag".to_string(),
+            );
+            let rgb = |color: &Color| (color.r, color.g, color.b);
+            check(
+                !self.highlight_collisions ||
+                    (rgb(&self.highlight_color) != rgb(&self.cut_color) &&
+                         rgb(&self.highlight_color) != rgb(&self.engrave_color)),
+                "the highlight color must differ from the cut and engrave colors, or the laser \
+                 would act on the collision marks"
+                    .to_string(),
+            );
+            check(
+                !self.note_labels || self.loop_padding.is_none(),
+                "a loop has no space before the first note for note labels".to_string(),
+            );
+            if self.note_labels {
+                let width = self.note_label_width();
+                check(
+                    width <= self.interior_margin_left,
+                    format!(
+                        "note labels need {:.2}mm of space before the first note, not {:.2}mm",
+                        width,
+                        self.interior_margin_left
+                    ),
+                );
+            }
+            check(
+                self.lead_in_height <= self.tape_height,
+                format!(
+                    "the lead-in ({}mm) is taller than the tape ({}mm)",
+                    self.lead_in_height,
+                    self.tape_height
+                ),
+            );
+            check(
+                self.margin_top + self.tape_height + self.margin_bottom <= self.page_height,
+                format!(
+                    "a {}mm tape does not fit between the top and bottom margins of a {}mm page",
+                    self.tape_height,
+                    self.page_height
+                ),
+            );
+            let widths = self.usable_widths();
+            for &(strip, width) in &[
+                ("first", widths.first_strip),
+                ("middle", widths.middle_strip),
+                ("last", widths.last_strip),
+            ]
+            {
+                check(
+                    positive(width),
+                    format!(
+                        "the margins, lead-in, join and hole size leave {:.2}mm for notes on the \
+                         {} strip of a tape",
+                        width,
+                        strip
+                    ),
+                );
+            }
+            check(
+                self.row_spacing.is_finite(),
+                format!("the row spacing must be a number: {}", self.row_spacing),
+            );
+            if self.notes.len() > 1 {
+                // Music box combs usually have rows closer together than a hole is wide, which
+                // only matters for neighboring notes played together. A hole that reaches the
+                // center of the next row would play both notes, though.
+                check(
+                    self.row_spacing > self.hole_radius,
+                    format!(
+                        "rows {:.2}mm apart are too close for holes {:.2}mm across",
+                        self.row_spacing,
+                        2.0 * self.hole_radius
+                    ),
+                );
+            }
+            check(
+                self.interior_margin_top >= self.hole_radius,
+                format!(
+                    "holes in the top row extend {:.2}mm past the edge of the tape",
+                    self.hole_radius - self.interior_margin_top
+                ),
+            );
+            let bottom_row = self.interior_margin_top +
+                self.notes.len().saturating_sub(1) as f64 * self.row_spacing;
+            check(
+                bottom_row + self.hole_radius <= self.tape_height,
+                format!(
+                    "holes in the bottom row extend {:.2}mm past the edge of the tape",
+                    bottom_row + self.hole_radius - self.tape_height
+                ),
+            );
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::InvalidOptions(problems))
+        }
+    }
+
+    /// Finds pairs of neighboring holes in the same row that are closer than
+    /// `min_hole_distance` or would merge when cut, along the whole tape and across the seam of
+    /// a loop.
+    pub fn find_collisions(&self, pages: &[Page]) -> Vec<Collision> {
+        let min_distance = self.min_hole_distance.max(2.0 * self.hole_radius);
+        let holes: Vec<(usize, &Hole)> = pages
+            .iter()
+            .enumerate()
+            .flat_map(|(page_num, page)| {
+                page.strips
+                    .iter()
+                    .flat_map(|strip| strip.holes.iter())
+                    .map(move |hole| (page_num, hole))
+            })
+            .collect();
+        let loop_length = if self.loop_padding.is_some() {
+            Some(self.tape_length(pages))
+        } else {
+            None
+        };
+        let mut collisions = Vec::new();
+        for row in 0..self.notes.len() {
+            // Holes near the ends of strips are repeated where the strips overlap, so compare
+            // each note once.
+            let mut notes: Vec<(f64, u64)> = holes
+                .iter()
+                .filter(|&&(_, hole)| hole.row == row)
+                .map(|&(_, hole)| (hole.offset, hole.tick))
+                .collect();
+            notes.sort_by(|a, b| a.0.total_cmp(&b.0));
+            notes.dedup_by_key(|note| note.1);
+            let mut pairs: Vec<(u64, u64, f64)> = notes
+                .windows(2)
+                .map(|pair| (pair[0].1, pair[1].1, pair[1].0 - pair[0].0))
+                .collect();
+            if let Some(length) = loop_length {
+                if notes.len() > 1 {
+                    let (first, last) = (notes[0], notes[notes.len() - 1]);
+                    pairs.push((last.1, first.1, first.0 + length - last.0));
                 }
-                writeln!(output, "</g>")?;
-                strip_num += 1;
             }
-            writeln!(output, "</g>")?;
-            writeln!(output, "</svg>")?;
+            for (first_tick, second_tick, distance) in pairs {
+                if distance >= min_distance {
+                    continue;
+                }
+                let occurrences = |tick| {
+                    holes.iter().filter(
+                        move |&&(_, hole)| hole.row == row && hole.tick == tick,
+                    )
+                };
+                // Show both holes on a strip that has them, or else where the second hole would
+                // be on the strip of the first.
+                let same_strip = occurrences(first_tick)
+                    .filter_map(|&(page, first)| {
+                        occurrences(second_tick)
+                            .find(|&&(_, second)| {
+                                second.strip == first.strip &&
+                                    (second.position.0 - first.position.0 - distance).abs() < 1e-6
+                            })
+                            .map(|&(_, second)| (page, first, second.position))
+                    })
+                    .next();
+                let (page, first, second) = same_strip.unwrap_or_else(|| {
+                    let (page, first) = occurrences(first_tick).next().cloned().unwrap();
+                    (page, first, (first.position.0 + distance, first.position.1))
+                });
+                collisions.push(Collision {
+                    page,
+                    strip: first.strip,
+                    row,
+                    first: first.position,
+                    second,
+                    distance,
+                });
+            }
         }
-        Ok(())
+        collisions.sort_by(|a, b| {
+            a.strip.cmp(&b.strip).then(a.first.0.total_cmp(&b.first.0))
+        });
+        collisions
     }
 
-    #[cfg(feature = "pdf")]
-    pub fn make_pdf(&self, pages: &[Page], output: &mut Write) -> io::Result<()> {
-        use std::io::Cursor;
-        use std::io::BufWriter;
-        use printpdf::*;
-        let (doc, page1, layer1) = PdfDocument::new(
-            self.title.clone(),
-            self.page_width,
-            self.page_height,
-            "Layer 1".to_string(),
-        );
-        let font = if pages.iter().flat_map(|p| p.strips.iter()).all(|s| {
-            s.texts.is_empty()
+    /// Returns the notes that `layout` places on the tape, sorted by time.
+    pub fn selected_notes(&self, smf: &SMF) -> Result<Vec<Note>, Error> {
+        let song = self.read_notes(smf)?;
+        let notes = self.playable_notes(song.notes, &mut Report::default())?;
+        self.thin_chords(song.div, notes, &mut Report::default())
+    }
+
+    /// Counts how often each note is played in the selected part of the song after transposing,
+    /// whether or not it is in `notes`. Sorted by note number, i.e. from the highest pitch down.
+    pub fn note_counts(&self, smf: &SMF) -> Result<Vec<NoteCount>, Error> {
+        let mut counts: Vec<NoteCount> = Vec::new();
+        for note in self.read_notes(smf)?.notes {
+            let n = transpose_note(note.note, self.transpose).unwrap_or(note.note);
+            match counts.iter().position(|c| c.note == n) {
+                Some(i) => counts[i].count += 1,
+                None => counts.push(NoteCount { note: n, count: 1 }),
+            }
+        }
+        counts.sort_by_key(|c| c.note);
+        Ok(counts)
+    }
+
+    /// Works out how long the tape for a song is, from the outlines that `layout` makes, and how
+    /// many strips and pages it takes.
+    pub fn tape_size(&self, smf: &SMF) -> Result<TapeSize, Error> {
+        let (pages, _) = self.lay_out(smf)?;
+        Ok(TapeSize {
+            length: self.tape_length(&pages),
+            strips: pages.iter().map(|page| page.strips.len()).sum(),
+            pages: pages.len(),
         })
-        {
+    }
+
+    /// Measures the tape from the outlines of its strips.
+    fn tape_length(&self, pages: &[Page]) -> f64 {
+        let strips: Vec<&Strip> = pages.iter().flat_map(|page| page.strips.iter()).collect();
+        let width: f64 = strips
+            .iter()
+            .map(|strip| {
+                let xs = strip.outline.iter().map(|point| point.0);
+                xs.clone().fold(f64::NEG_INFINITY, f64::max) - xs.fold(f64::INFINITY, f64::min)
+            })
+            .sum();
+        // Neighboring strips overlap by the width of a join, and so do the ends of a loop.
+        let seams = if self.loop_padding.is_some() {
+            strips.len()
+        } else {
+            strips.len().saturating_sub(1)
+        };
+        width - seams as f64 * self.effective_join_width()
+    }
+
+    /// Replaces `notes` with exactly the notes the song plays, keeping the space above the top
+    /// row and below the bottom row and spacing the rows evenly between them.
+    pub fn fit_notes_to_song(&mut self, smf: &SMF) -> Result<(), Error> {
+        let notes: Vec<u8> = self.note_counts(smf)?.iter().map(|c| c.note).collect();
+        if notes.is_empty() {
+            return Err(Error::EmptyTrack);
+        }
+        let rows_height = self.row_spacing * (self.notes.len() as f64 - 1.0);
+        // A single row has no spacing to adjust.
+        if notes.len() > 1 {
+            self.row_spacing = rows_height / (notes.len() as f64 - 1.0);
+        }
+        self.notes = notes;
+        Ok(())
+    }
+
+    /// Tries every transposition from `min_shift` to `max_shift` semitones and reports how many
+    /// of the song's notes each one leaves unplayable.
+    pub fn transpositions(
+        &self,
+        smf: &SMF,
+        min_shift: i8,
+        max_shift: i8,
+    ) -> Result<Vec<Transposition>, Error> {
+        let notes = self.read_notes(smf)?.notes;
+        Ok(
+            (min_shift..max_shift.saturating_add(1))
+                .map(|shift| {
+                    Transposition {
+                        shift,
+                        unplayable: notes
+                            .iter()
+                            .filter(|note| match transpose_note(note.note, shift) {
+                                Some(n) => !self.notes.contains(&n),
+                                None => true,
+                            })
+                            .count(),
+                    }
+                })
+                .collect(),
+        )
+    }
+
+    /// Reads the selected notes from `smf`, with times relative to the start of the tape.
+    fn read_notes(&self, smf: &SMF) -> Result<Song, Error> {
+        use Error::*;
+        let mut div = ticks_per_stretch_unit(smf.division)?;
+        let grid = match self.quantize {
+            Some(grid) => Some(grid.ticks(smf.division)?),
+            None => None,
+        };
+        // Time-code based files are already in real time.
+        let tempo_changes = if self.tempo_map && smf.division > 0 {
+            Some(tempo_changes(smf))
+        } else {
             None
-        } else if self.font_file.is_some() {
-            Some(
-                doc.add_external_font(File::open(self.font_file.as_ref().unwrap())?)
-                    .expect("Failed to load font"),
-            )
+        };
+        let track_nums = match self.tracks {
+            Some(ref tracks) => tracks.clone(),
+            None => (0..smf.tracks.len()).collect(),
+        };
+        if track_nums.iter().any(|&t| t >= smf.tracks.len()) {
+            return Err(TrackNotFound);
+        }
+        let start = match self.start {
+            Some(position) => Some(position.ticks(smf)?),
+            None => None,
+        };
+        let end = match self.end {
+            Some(position) => Some(position.ticks(smf)?),
+            None => None,
+        };
+        let in_range = |time| start.map_or(true, |start| time >= start) &&
+            end.map_or(true, |end| time < end);
+
+        let mut notes = {
+            let mut notes = Vec::new();
+            for &track_num in &track_nums {
+                let mut time = 0;
+                for event in &smf.tracks[track_num].events {
+                    time += event.vtime;
+                    match event.event {
+                        Event::Midi(ref msg) => {
+                            if msg.status() == Status::NoteOn && self.uses_channel(msg.channel()) &&
+                                self.is_audible(msg.data(2))
+                            {
+                                let note = Note {
+                                    time: match grid {
+                                        Some(grid) => self.snap_to_grid(time, grid),
+                                        None => time,
+                                    },
+                                    note: midi_note(msg.data(1)),
+                                    tick: time,
+                                };
+                                if in_range(note.time) {
+                                    notes.push(note);
+                                }
+                            }
+                        }
+                        Event::Meta(_) => {}
+                    };
+                }
+            }
+            notes
+        };
+        if notes.is_empty() {
+            return Err(EmptyTrack);
+        }
+        let (mut start, mut end) = (start, end.unwrap_or(0));
+        if let Some(padding) = self.loop_padding {
+            let first = start.unwrap_or_else(|| notes.iter().map(|note| note.time).min().unwrap());
+            let last = notes.iter().map(|note| note.time).max().unwrap();
+            // The loop must end after the last note so that it does not land on the first.
+            let length = end.saturating_sub(first).max(last - first + 1);
+            start = Some(first);
+            end = padding.end(smf, first, first + length)?;
+        }
+        let mut beats = if self.draw_grid {
+            let last = notes.iter().map(|note| note.time).max().unwrap();
+            beats(smf, last.max(end))
         } else {
-            Some(doc.add_builtin_font(BuiltinFont::TimesRoman).expect(
-                "Failed to load built-in font",
-            ))
+            Vec::new()
         };
-        for (page_num, page) in pages.iter().enumerate() {
-            let (page_idx, layer_idx) = if page_num == 0 {
-                (page1, layer1)
-            } else {
-                doc.add_page(self.page_width, self.page_height, "Layer 1".to_string())
+        if let Some(ref tempo_changes) = tempo_changes {
+            for note in notes.iter_mut() {
+                note.time = ticks_to_micros(tempo_changes, div, note.time);
+            }
+            for beat in beats.iter_mut() {
+                beat.0 = ticks_to_micros(tempo_changes, div, beat.0);
+            }
+            start = start.map(|start| ticks_to_micros(tempo_changes, div, start));
+            end = ticks_to_micros(tempo_changes, div, end);
+            div = MICROS_PER_SECOND;
+        }
+        notes.sort_unstable();
+        // The same note played at the same time on several tracks (or snapped to the same grid
+        // position) only needs one hole.
+        notes.dedup_by_key(|note| (note.time, note.note));
+        // Without an explicit start, the tape starts at the first note.
+        let start = start.unwrap_or(notes[0].time);
+        for note in notes.iter_mut() {
+            note.time -= start;
+        }
+        beats.retain(|&(time, _)| time >= start);
+        for beat in beats.iter_mut() {
+            beat.0 -= start;
+        }
+        Ok(Song {
+            div,
+            notes,
+            end: end.saturating_sub(start),
+            beats,
+        })
+    }
+
+    /// Transposes `notes` and applies `missing_notes` to any that are not available.
+    fn playable_notes(&self, notes: Vec<Note>, report: &mut Report) -> Result<Vec<Note>, Error> {
+        let mut playable = Vec::with_capacity(notes.len());
+        for note in notes {
+            let transposed = transpose_note(note.note, self.transpose);
+            if let Some(n) = transposed {
+                if self.notes.contains(&n) {
+                    playable.push(Note { note: n, ..note });
+                    continue;
+                }
+            }
+            let original = transposed.unwrap_or(note.note);
+            let replacement = match (self.missing_notes, transposed) {
+                (MissingNotePolicy::Fail, _) => return Err(Error::InvalidNote(original)),
+                (_, None) | (MissingNotePolicy::Drop, _) => None,
+                (MissingNotePolicy::Fold, Some(n)) => self.fold_note(n),
+                (MissingNotePolicy::Nearest, Some(n)) => self.nearest_note(n),
             };
-            let cur_layer = doc.get_page(page_idx).get_layer(layer_idx);
-            cur_layer.set_outline_color(printpdf::Color::Rgb(Rgb::new(
-                self.cut_color.r as f64 / 255.0,
-                self.cut_color.g as f64 / 255.0,
-                self.cut_color.b as f64 / 255.0,
-                None,
-            )));
-            cur_layer.set_outline_thickness(self.cut_stroke_width);
-            for strip in &page.strips {
-                cur_layer.add_shape(Line::new(
-                    // The outline should be grown by 1/2 line thickness to achieve the desired
-                    // size after cutting.
-                    strip
-                        .outline
-                        .iter()
-                        .map(|&(x, y)| (Point::new(x, self.page_height - y), false))
-                        .collect(),
-                    /* has_stroke*/
-                    true,
-                    /* is_closed */
-                    true,
-                    /* has_fill */
-                    false,
-                ));
-                for text in &strip.texts {
-                    cur_layer.use_text(
-                        text.text.clone(),
-                        mm_to_pt!(text.font_size) as i64,
-                        text.position.0,
-                        self.page_height - text.position.1,
-                        font.as_ref().unwrap(),
-                    );
+            report.substitutions.push(Substitution {
+                tick: note.tick,
+                original,
+                replacement,
+            });
+            if let Some(n) = replacement {
+                playable.push(Note { note: n, ..note });
+            }
+        }
+        // Substitutes may coincide with notes that were already there.
+        playable.sort_unstable();
+        playable.dedup_by_key(|note| (note.time, note.note));
+        if playable.is_empty() {
+            return Err(Error::EmptyTrack);
+        }
+        Ok(playable)
+    }
+
+    /// Drops notes from chords with more than `max_polyphony` notes according to
+    /// `chord_strategy`. `notes` must be sorted by time.
+    fn thin_chords(
+        &self,
+        div: f64,
+        notes: Vec<Note>,
+        report: &mut Report,
+    ) -> Result<Vec<Note>, Error> {
+        let max_polyphony = match self.max_polyphony {
+            Some(max_polyphony) => max_polyphony,
+            None => return Ok(notes),
+        };
+        let mut kept = Vec::with_capacity(notes.len());
+        let mut start = 0;
+        while start < notes.len() {
+            let mut end = start + 1;
+            while end < notes.len() &&
+                self.time_to_width(div, notes[end].time - notes[start].time) <= self.chord_window
+            {
+                end += 1;
+            }
+            let mut chord = notes[start..end].to_vec();
+            if chord.len() > max_polyphony {
+                // Higher notes have lower numbers, so highest first is ascending order.
+                chord.sort_by_key(|note| note.note);
+                match self.chord_strategy {
+                    ChordStrategy::Highest => {}
+                    ChordStrategy::Lowest => chord.reverse(),
+                    ChordStrategy::Melody => if chord.len() > 2 {
+                        let lowest = chord.pop().unwrap();
+                        chord.insert(1, lowest);
+                    },
                 }
-                for hole in &strip.holes {
-                    let x = hole.0;
-                    let y = self.page_height - hole.1;
-                    let r = self.hole_radius - self.cut_stroke_width;
-                    cur_layer.add_shape(Line::new(
-                        vec![
-                            (Point::new(x - r, y - r), false),
-                            (Point::new(x + r, y - r), false),
-                            (Point::new(x + r, y + r), false),
-                            (Point::new(x - r, y + r), false),
-                        ],
-                        /* has_stroke*/
-                        true,
-                        /* is_closed */
-                        true,
-                        /* has_fill */
-                        false,
-                    ));
+                for note in chord.drain(max_polyphony..) {
+                    report.dropped_notes.push(DroppedNote {
+                        tick: note.tick,
+                        note: note.note,
+                    });
                 }
+                chord.sort_unstable();
             }
+            kept.extend(chord);
+            start = end;
         }
-        // Using a BufWriter to a Cursor is wasteful, but it allows this to work for any Write
-        // without having to guarantee that output implements Seek.
-        let mut buffer = Cursor::new(Vec::<u8>::new());
-        doc.save(&mut BufWriter::new(&mut buffer)).expect(
-            "Failed to generate PDF",
-        );
-        output.write_all(&buffer.into_inner())
+        if kept.is_empty() {
+            return Err(Error::EmptyTrack);
+        }
+        Ok(kept)
     }
 
-    fn time_to_width(&self, div: i16, time: u64) -> f64 {
-        return time as f64 * self.stretch / div as f64;
+    /// Returns the available note that is the fewest octaves away from `note`, if any.
+    fn fold_note(&self, note: u8) -> Option<u8> {
+        // Lower notes have higher numbers, so ties go to the highest number.
+        self.notes
+            .iter()
+            .filter(|&&n| (n as i16 - note as i16) % 12 == 0)
+            .min_by_key(|&&n| ((n as i16 - note as i16).abs(), Reverse(n)))
+            .cloned()
     }
 
-    fn polygon(&self, points: &[Point], output: &mut Write) {
-        write!(
-            output,
-            r#"<polygon points="{:2},{:2}"#,
-            points[0].0,
-            points[0].1
-        ).unwrap();
-        for i in 1..points.len() {
-            write!(output, " {:.2},{:.2}", points[i].0, points[i].1).unwrap();
+    /// Returns the available note closest in pitch to `note`.
+    fn nearest_note(&self, note: u8) -> Option<u8> {
+        self.notes
+            .iter()
+            .min_by_key(|&&n| ((n as i16 - note as i16).abs(), Reverse(n)))
+            .cloned()
+    }
+
+    fn snap_to_grid(&self, time: u64, grid: f64) -> u64 {
+        let position = (time as f64 / grid).round();
+        let swing = if position % 2.0 == 1.0 {
+            self.swing * grid
+        } else {
+            0.0
+        };
+        (position * grid + swing).round() as u64
+    }
+
+    fn uses_channel(&self, channel: u8) -> bool {
+        match self.channels {
+            Some(ref channels) => channels.contains(&channel),
+            None => true,
         }
-        writeln!(output, r#""/>"#).unwrap();
     }
 
-    #[allow(dead_code)]
-    fn line(&self, start: Point, end: Point, output: &mut Write) {
+    fn is_audible(&self, velocity: u8) -> bool {
+        // Many sequencers encode note-off as NoteOn with velocity 0.
+        velocity > 0 && velocity >= self.min_velocity
+    }
+
+    /// Width of the joins between strips, which straight joins do not have.
+    fn effective_join_width(&self) -> f64 {
+        if self.join_style == JoinStyle::Straight {
+            0.0
+        } else {
+            self.join_width
+        }
+    }
+
+    fn usable_widths(&self) -> UsableWidths {
+        let join_width = self.effective_join_width();
+        let page_width = self.page_width - self.margin_left - self.margin_right;
+        UsableWidths {
+            first_strip: page_width - self.lead_in_width - self.interior_margin_left -
+                self.hole_radius - join_width,
+            middle_strip: page_width - join_width,
+            last_strip: page_width - self.interior_margin_right - self.hole_radius,
+            only_strip: page_width - self.lead_in_width - self.interior_margin_left -
+                self.interior_margin_right - (2.0 * self.hole_radius),
+        }
+    }
+
+    /// Returns how many strips a song `total_width` long takes, and how many fit on a page.
+    fn strip_counts(&self, total_width: f64) -> (u16, u16) {
+        let UsableWidths {
+            first_strip,
+            middle_strip,
+            last_strip,
+            only_strip,
+        } = self.usable_widths();
+        let num_strips = if self.loop_padding.is_some() {
+            ((total_width / middle_strip).ceil() as u16).max(1)
+        } else if total_width <= only_strip {
+            1
+        } else {
+            2 + ((total_width - first_strip - last_strip) / middle_strip).ceil() as u16
+        };
+        let strips_per_page = 1 +
+            ((self.page_height - self.margin_top - self.margin_bottom - self.tape_height) /
+                 (self.gap + self.tape_height))
+                .floor() as u16;
+        (num_strips, strips_per_page)
+    }
+
+    /// Width of an engraved grid line.
+    pub fn grid_line_width(&self, line: &GridLine) -> f64 {
+        if line.bar {
+            self.cut_stroke_width * 4.0
+        } else {
+            self.cut_stroke_width * 2.0
+        }
+    }
+
+    fn time_to_width(&self, div: f64, time: u64) -> f64 {
+        time as f64 * self.stretch / div
+    }
+
+    fn polygon(&self, points: &[Point], output: &mut dyn Write) -> io::Result<()> {
+        write!(output, r#"<polygon points=""#)?;
+        for (i, point) in points.iter().enumerate() {
+            if i > 0 {
+                write!(output, " ")?;
+            }
+            write!(output, "{:.2},{:.2}", point.0, point.1)?;
+        }
+        writeln!(output, r#""/>"#)
+    }
+
+    fn line(&self, start: Point, end: Point, output: &mut dyn Write) -> io::Result<()> {
         writeln!(
             output,
             r#"<line x1="{x1:.2}" y1="{y1:.2}" x2="{x2:.2}" y2="{y2:.2}" />"#,
@@ -479,7 +2308,7 @@ impl Options {
             y1 = start.1,
             x2 = end.0,
             y2 = end.1,
-        ).unwrap();
+        )
     }
 
     fn join(&self, x: f64, y: f64) -> Vec<Point> {
@@ -506,3 +2335,1002 @@ impl Options {
         points
     }
 }
+
+/// An output backend. `render` walks a layout and calls these methods for each page, strip and
+/// shape in turn. Cut shapes go all the way through the tape and engraved shapes only mark it.
+const NO_PAGE: &str = "no page has been started";
+
+pub trait Renderer {
+    /// Called once before the first page.
+    fn begin(&mut self, _pages: &[Page]) -> Result<(), Error> {
+        Ok(())
+    }
+    fn begin_page(&mut self, page_num: usize, page: &Page) -> Result<(), Error>;
+    fn end_page(&mut self, page_num: usize) -> Result<(), Error>;
+    fn begin_strip(&mut self, _strip_num: usize) -> Result<(), Error> {
+        Ok(())
+    }
+    fn end_strip(&mut self, _strip_num: usize) -> Result<(), Error> {
+        Ok(())
+    }
+    /// Called first for every strip.
+    fn cut_outline(&mut self, outline: &[Point]) -> Result<(), Error>;
+    fn cut_hole(&mut self, hole: &Hole) -> Result<(), Error>;
+    fn engrave_line(&mut self, line: &GridLine) -> Result<(), Error>;
+    fn engrave_text(&mut self, text: &Text) -> Result<(), Error>;
+    /// Called once after the last page.
+    fn finish(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Makes a renderer that asks for a new output stream for each page number (or only page 0 if
+/// the format writes all pages to one stream).
+pub type RendererFactory = for<'a> fn(
+    &'a Options,
+    &'a mut dyn FnMut(usize) -> io::Result<Box<dyn Write>>,
+) -> Box<dyn Renderer + 'a>;
+
+/// An output format that can be selected by name or by file name extension.
+#[derive(Clone, Copy)]
+pub struct OutputFormat {
+    pub name: &'static str,
+    pub extension: &'static str,
+    pub make_renderer: RendererFactory,
+}
+
+/// Returns every output format that was enabled at compile time.
+pub fn output_formats() -> Vec<OutputFormat> {
+    let mut formats = vec![
+        OutputFormat {
+            name: "SVG",
+            extension: "svg",
+            make_renderer: svg_renderer,
+        },
+        OutputFormat {
+            name: "JSON",
+            extension: "json",
+            make_renderer: json_renderer,
+        },
+    ];
+    #[cfg(feature = "pdf")]
+    formats.push(OutputFormat {
+        name: "PDF",
+        extension: "pdf",
+        make_renderer: pdf_renderer,
+    });
+    #[cfg(feature = "dxf")]
+    formats.push(OutputFormat {
+        name: "DXF",
+        extension: "dxf",
+        make_renderer: dxf_renderer,
+    });
+    formats
+}
+
+fn svg_renderer<'a>(
+    options: &'a Options,
+    make_output_stream: &'a mut dyn FnMut(usize) -> io::Result<Box<dyn Write>>,
+) -> Box<dyn Renderer + 'a> {
+    Box::new(SvgRenderer::new(options, make_output_stream))
+}
+
+fn json_renderer<'a>(
+    _options: &'a Options,
+    make_output_stream: &'a mut dyn FnMut(usize) -> io::Result<Box<dyn Write>>,
+) -> Box<dyn Renderer + 'a> {
+    Box::new(JsonRenderer::new(make_output_stream))
+}
+
+#[cfg(feature = "pdf")]
+fn pdf_renderer<'a>(
+    options: &'a Options,
+    make_output_stream: &'a mut dyn FnMut(usize) -> io::Result<Box<dyn Write>>,
+) -> Box<dyn Renderer + 'a> {
+    Box::new(PdfRenderer::new(options, make_output_stream))
+}
+
+#[cfg(feature = "dxf")]
+fn dxf_renderer<'a>(
+    options: &'a Options,
+    make_output_stream: &'a mut dyn FnMut(usize) -> io::Result<Box<dyn Write>>,
+) -> Box<dyn Renderer + 'a> {
+    Box::new(DxfRenderer::new(options, make_output_stream))
+}
+
+/// Draws every page of a layout with `renderer`.
+pub fn render(pages: &[Page], renderer: &mut dyn Renderer) -> Result<(), Error> {
+    renderer.begin(pages)?;
+    let mut strip_num = 0;
+    for (page_num, page) in pages.iter().enumerate() {
+        renderer.begin_page(page_num, page)?;
+        for strip in &page.strips {
+            renderer.begin_strip(strip_num)?;
+            renderer.cut_outline(&strip.outline[..])?;
+            for line in &strip.grid {
+                renderer.engrave_line(line)?;
+            }
+            for text in &strip.texts {
+                renderer.engrave_text(text)?;
+            }
+            for hole in &strip.holes {
+                renderer.cut_hole(hole)?;
+            }
+            renderer.end_strip(strip_num)?;
+            strip_num += 1;
+        }
+        renderer.end_page(page_num)?;
+    }
+    renderer.finish()
+}
+
+/// Writes each page to its own SVG file.
+pub struct SvgRenderer<'a> {
+    options: &'a Options,
+    make_output_stream: &'a mut dyn FnMut(usize) -> io::Result<Box<dyn Write>>,
+    output: Option<Box<dyn Write>>,
+    strip_num: usize,
+    collisions: Vec<Collision>,
+}
+
+impl<'a> SvgRenderer<'a> {
+    pub fn new(
+        options: &'a Options,
+        make_output_stream: &'a mut dyn FnMut(usize) -> io::Result<Box<dyn Write>>,
+    ) -> SvgRenderer<'a> {
+        SvgRenderer {
+            options,
+            make_output_stream,
+            output: None,
+            strip_num: 0,
+            collisions: Vec::new(),
+        }
+    }
+
+    fn output(&mut self) -> Result<&mut dyn Write, Error> {
+        match self.output {
+            Some(ref mut output) => Ok(output),
+            None => Err(Error::OutOfOrder(NO_PAGE)),
+        }
+    }
+}
+
+impl<'a> Renderer for SvgRenderer<'a> {
+    fn begin(&mut self, pages: &[Page]) -> Result<(), Error> {
+        // Collisions can span strips and pages, so find them all up front.
+        if self.options.highlight_collisions {
+            self.collisions = self.options.find_collisions(pages);
+        }
+        Ok(())
+    }
+
+    fn begin_page(&mut self, page_num: usize, _page: &Page) -> Result<(), Error> {
+        let options = self.options;
+        self.output = Some((self.make_output_stream)(page_num)?);
+        let output = self.output()?;
+        writeln!(output, r#"<?xml version="1.0" encoding="UTF-8" ?>"#)?;
+        writeln!(
+            output,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" version="1.1" width="{page_width:.2}mm" height="{page_height:.2}mm" viewBox="0 0 {page_width:.2} {page_height:.2}">"#,
+            page_width = options.page_width,
+            page_height = options.page_height
+        )?;
+        writeln!(
+            output,
+            r#"<g fill="none" stroke-width="{cut_stroke_width:.2}" stroke="rgba({r},{g},{b},{a:.2})">"#,
+            cut_stroke_width = options.cut_stroke_width,
+            r = options.cut_color.r,
+            g = options.cut_color.g,
+            b = options.cut_color.b,
+            a = options.cut_color.a,
+        )?;
+        Ok(())
+    }
+
+    fn end_page(&mut self, page_num: usize) -> Result<(), Error> {
+        let options = self.options;
+        let mut output = self.output.take().ok_or(Error::OutOfOrder(NO_PAGE))?;
+        writeln!(output, "</g>")?;
+        let collisions: Vec<&Collision> =
+            self.collisions.iter().filter(|c| c.page == page_num).collect();
+        if !collisions.is_empty() {
+            // Cover each pair of colliding holes with a translucent capsule.
+            writeln!(
+                output,
+                r#"<g stroke-width="{width:.2}" stroke-linecap="round" stroke-opacity="0.5" stroke="rgba({r},{g},{b},{a:.2})">"#,
+                width = options.hole_radius * 4.0,
+                r = options.highlight_color.r,
+                g = options.highlight_color.g,
+                b = options.highlight_color.b,
+                a = options.highlight_color.a,
+            )?;
+            for collision in collisions {
+                options.line(collision.first, collision.second, &mut output)?;
+            }
+            writeln!(output, "</g>")?;
+        }
+        writeln!(output, "</svg>")?;
+        Ok(())
+    }
+
+    fn begin_strip(&mut self, strip_num: usize) -> Result<(), Error> {
+        self.strip_num = strip_num;
+        Ok(())
+    }
+
+    fn end_strip(&mut self, _strip_num: usize) -> Result<(), Error> {
+        writeln!(self.output()?, "</g>")?;
+        Ok(())
+    }
+
+    fn cut_outline(&mut self, outline: &[Point]) -> Result<(), Error> {
+        let (options, strip_num) = (self.options, self.strip_num);
+        let output = self.output()?;
+        writeln!(
+            output,
+            r#"<defs><clipPath id="strip_{}_border">"#,
+            strip_num
+        )?;
+        options.polygon(outline, output)?;
+        writeln!(output, "</clipPath></defs>")?;
+        writeln!(
+            output,
+            r#"<g clip-path="url(#strip_{}_border)">"#,
+            strip_num
+        )?;
+
+        // Draw the border with double the stroke width then clip the half of that inside
+        // the border producing exactly the requested strip size (assuming that stroke
+        // width is exactly equal to the kerf.
+        writeln!(
+            output,
+            r#"<g stroke-width="{:.2}">"#,
+            options.cut_stroke_width * 2.0
+        )?;
+        options.polygon(outline, output)?;
+        writeln!(output, "</g>")?;
+        Ok(())
+    }
+
+    fn cut_hole(&mut self, hole: &Hole) -> Result<(), Error> {
+        let options = self.options;
+        writeln!(
+            self.output()?,
+            r#"<circle cx="{x:.2}" cy="{y:.2}" r="{hole_radius:.2}" />"#,
+            x = hole.position.0,
+            y = hole.position.1,
+            // Reduce the radius by 1/2 the kerf to create a resulting hole of the exact
+            // size requested.
+            hole_radius = options.hole_radius - (options.cut_stroke_width / 2.0),
+        )?;
+        Ok(())
+    }
+
+    fn engrave_line(&mut self, line: &GridLine) -> Result<(), Error> {
+        let options = self.options;
+        writeln!(
+            self.output()?,
+            r#"<line x1="{x1:.2}" y1="{y1:.2}" x2="{x2:.2}" y2="{y2:.2}" stroke-width="{width:.2}" stroke="rgba({r},{g},{b},{a:.2})" />"#,
+            x1 = line.start.0,
+            y1 = line.start.1,
+            x2 = line.end.0,
+            y2 = line.end.1,
+            width = options.grid_line_width(line),
+            r = options.engrave_color.r,
+            g = options.engrave_color.g,
+            b = options.engrave_color.b,
+            a = options.engrave_color.a,
+        )?;
+        Ok(())
+    }
+
+    fn engrave_text(&mut self, text: &Text) -> Result<(), Error> {
+        let options = self.options;
+        writeln!(
+            self.output()?,
+            r#"<text x="{x:.2}" y="{y:.2}" font-size="{font_size:.2}" fill="rgba({r},{g},{b},{a:.2})" stroke="none">{text}</text>"#,
+            x = text.position.0,
+            y = text.position.1,
+            font_size = text.font_size,
+            text = text.text,
+            r = options.engrave_color.r,
+            g = options.engrave_color.g,
+            b = options.engrave_color.b,
+            a = options.engrave_color.a,
+        )?;
+        Ok(())
+    }
+}
+
+/// Writes the layout itself as JSON, for use by other programs.
+pub struct JsonRenderer<'a> {
+    make_output_stream: &'a mut dyn FnMut(usize) -> io::Result<Box<dyn Write>>,
+    pages: Vec<Page>,
+}
+
+impl<'a> JsonRenderer<'a> {
+    pub fn new(make_output_stream: &'a mut dyn FnMut(usize) -> io::Result<Box<dyn Write>>) -> JsonRenderer<'a> {
+        JsonRenderer {
+            make_output_stream,
+            pages: Vec::new(),
+        }
+    }
+}
+
+impl<'a> Renderer for JsonRenderer<'a> {
+    fn begin_page(&mut self, _page_num: usize, page: &Page) -> Result<(), Error> {
+        self.pages.push(page.clone());
+        Ok(())
+    }
+
+    fn end_page(&mut self, _page_num: usize) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn cut_outline(&mut self, _outline: &[Point]) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn cut_hole(&mut self, _hole: &Hole) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn engrave_line(&mut self, _line: &GridLine) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn engrave_text(&mut self, _text: &Text) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        let output = (self.make_output_stream)(0)?;
+        serde_json::to_writer_pretty(output, &self.pages).map_err(io::Error::from)?;
+        Ok(())
+    }
+}
+
+/// Writes all pages to a single PDF document.
+#[cfg(feature = "pdf")]
+pub struct PdfRenderer<'a> {
+    options: &'a Options,
+    make_output_stream: &'a mut dyn FnMut(usize) -> io::Result<Box<dyn Write>>,
+    doc: Option<printpdf::PdfDocumentReference>,
+    layer: Option<printpdf::PdfLayerReference>,
+    font: Option<printpdf::IndirectFontRef>,
+}
+
+#[cfg(feature = "pdf")]
+impl<'a> PdfRenderer<'a> {
+    pub fn new(
+        options: &'a Options,
+        make_output_stream: &'a mut dyn FnMut(usize) -> io::Result<Box<dyn Write>>,
+    ) -> PdfRenderer<'a> {
+        let (doc, page1, layer1) = printpdf::PdfDocument::new(
+            options.title.clone(),
+            options.page_width,
+            options.page_height,
+            "Layer 1".to_string(),
+        );
+        // The document is created with its first page.
+        let layer = doc.get_page(page1).get_layer(layer1);
+        PdfRenderer {
+            options,
+            make_output_stream,
+            doc: Some(doc),
+            layer: Some(layer),
+            font: None,
+        }
+    }
+
+    fn doc(&self) -> Result<&printpdf::PdfDocumentReference, Error> {
+        self.doc.as_ref().ok_or(Error::OutOfOrder("the PDF has already been saved"))
+    }
+
+    /// Sets the color and width of the lines drawn next.
+    fn layer(&self, color: &Color, thickness: f64) -> Result<&printpdf::PdfLayerReference, Error> {
+        use printpdf::Rgb;
+        let layer = self.layer.as_ref().ok_or(Error::OutOfOrder(NO_PAGE))?;
+        layer.set_outline_color(printpdf::Color::Rgb(Rgb::new(
+            color.r as f64 / 255.0,
+            color.g as f64 / 255.0,
+            color.b as f64 / 255.0,
+            None,
+        )));
+        layer.set_outline_thickness(thickness);
+        Ok(layer)
+    }
+
+    /// Draws a polyline, with page coordinates flipped to PDF's upward pointing y axis. A point
+    /// marked true is followed by the control points of a Bezier curve.
+    fn shape(&self, layer: &printpdf::PdfLayerReference, points: &[(Point, bool)], closed: bool) {
+        layer.add_shape(printpdf::Line::new(
+            points
+                .iter()
+                .map(|&((x, y), bezier)| {
+                    (printpdf::Point::new(x, self.options.page_height - y), bezier)
+                })
+                .collect(),
+            /* has_stroke*/
+            true,
+            /* is_closed */
+            closed,
+            /* has_fill */
+            false,
+        ));
+    }
+}
+
+#[cfg(feature = "pdf")]
+impl<'a> Renderer for PdfRenderer<'a> {
+    fn begin_page(&mut self, page_num: usize, _page: &Page) -> Result<(), Error> {
+        let options = self.options;
+        if page_num > 0 {
+            let (page_idx, layer_idx) = self.doc()?.add_page(
+                options.page_width,
+                options.page_height,
+                "Layer 1".to_string(),
+            );
+            self.layer = Some(self.doc()?.get_page(page_idx).get_layer(layer_idx));
+        }
+        Ok(())
+    }
+
+    fn end_page(&mut self, _page_num: usize) -> Result<(), Error> {
+        self.layer = None;
+        Ok(())
+    }
+
+    fn cut_outline(&mut self, outline: &[Point]) -> Result<(), Error> {
+        let options = self.options;
+        let layer = self.layer(&options.cut_color, options.cut_stroke_width)?;
+        // The outline is grown by 1/2 line thickness to achieve the desired size after cutting.
+        let points: Vec<_> = grow_polygon(outline, options.cut_stroke_width / 2.0)
+            .into_iter()
+            .map(|point| (point, false))
+            .collect();
+        self.shape(layer, &points[..], true);
+        Ok(())
+    }
+
+    fn cut_hole(&mut self, hole: &Hole) -> Result<(), Error> {
+        let options = self.options;
+        let layer = self.layer(&options.cut_color, options.cut_stroke_width)?;
+        let (x, y) = hole.position;
+        // Reduce the radius by 1/2 the kerf to create a resulting hole of the exact size
+        // requested (same as SVG).
+        let r = options.hole_radius - (options.cut_stroke_width / 2.0);
+        // Distance from each quadrant's end point to its Bezier control points.
+        let k = r * BEZIER_CIRCLE_KAPPA;
+        self.shape(
+            layer,
+            &[
+                ((x + r, y), true),
+                ((x + r, y - k), true),
+                ((x + k, y - r), false),
+                ((x, y - r), true),
+                ((x - k, y - r), true),
+                ((x - r, y - k), false),
+                ((x - r, y), true),
+                ((x - r, y + k), true),
+                ((x - k, y + r), false),
+                ((x, y + r), true),
+                ((x + k, y + r), true),
+                ((x + r, y + k), false),
+                ((x + r, y), false),
+            ],
+            true,
+        );
+        Ok(())
+    }
+
+    fn engrave_line(&mut self, line: &GridLine) -> Result<(), Error> {
+        let options = self.options;
+        let layer = self.layer(&options.engrave_color, options.grid_line_width(line))?;
+        self.shape(layer, &[(line.start, false), (line.end, false)], false);
+        Ok(())
+    }
+
+    fn engrave_text(&mut self, text: &Text) -> Result<(), Error> {
+        use printpdf::BuiltinFont;
+        let options = self.options;
+        if self.font.is_none() {
+            self.font = Some(match options.font_file {
+                Some(ref font_file) => {
+                    self.doc()?
+                        .add_external_font(File::open(font_file).map_err(|e| {
+                            Error::Font(format!("{}: {}", font_file, e))
+                        })?)
+                        .map_err(|e| Error::Font(e.to_string()))?
+                }
+                None => {
+                    self.doc()?
+                        .add_builtin_font(BuiltinFont::TimesRoman)
+                        .map_err(|e| Error::Pdf(e.to_string()))?
+                }
+            });
+        }
+        let layer = self.layer.as_ref().ok_or(Error::OutOfOrder(NO_PAGE))?;
+        layer.use_text(
+            text.text.clone(),
+            mm_to_pt!(text.font_size) as i64,
+            text.position.0,
+            options.page_height - text.position.1,
+            self.font.as_ref().unwrap(),
+        );
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        use std::io::BufWriter;
+        use std::io::Cursor;
+        let doc = self.doc.take().ok_or(Error::OutOfOrder("the PDF has already been saved"))?;
+        // Using a BufWriter to a Cursor is wasteful, but it allows this to work for any Write
+        // without having to guarantee that output implements Seek.
+        let mut buffer = Cursor::new(Vec::<u8>::new());
+        doc.save(&mut BufWriter::new(&mut buffer)).map_err(
+            |e| Error::Pdf(e.to_string()),
+        )?;
+        (self.make_output_stream)(0)?.write_all(&buffer.into_inner())?;
+        Ok(())
+    }
+}
+
+/// Writes each page to its own DXF file, with cut and engraved shapes on separate layers.
+#[cfg(feature = "dxf")]
+pub struct DxfRenderer<'a> {
+    options: &'a Options,
+    make_output_stream: &'a mut dyn FnMut(usize) -> io::Result<Box<dyn Write>>,
+    drawing: Option<dxf::Drawing>,
+}
+
+#[cfg(feature = "dxf")]
+impl<'a> DxfRenderer<'a> {
+    const CUT_LAYER: &'static str = "Cut";
+    const ENGRAVE_LAYER: &'static str = "Engrave";
+
+    pub fn new(
+        options: &'a Options,
+        make_output_stream: &'a mut dyn FnMut(usize) -> io::Result<Box<dyn Write>>,
+    ) -> DxfRenderer<'a> {
+        DxfRenderer {
+            options,
+            make_output_stream,
+            drawing: None,
+        }
+    }
+
+    fn add_entity(
+        &mut self,
+        specific: dxf::entities::EntityType,
+        layer: &str,
+        color: &Color,
+    ) -> Result<(), Error> {
+        let mut entity = dxf::entities::Entity::new(specific);
+        entity.common.layer = layer.to_string();
+        // DXF has no notion of stroke color outside of layers and true-color (R2004+) entities.
+        entity.common.color_24_bit = ((color.r as i32) << 16) | ((color.g as i32) << 8) |
+            (color.b as i32);
+        self.drawing
+            .as_mut()
+            .ok_or(Error::OutOfOrder(NO_PAGE))?
+            .add_entity(entity);
+        Ok(())
+    }
+
+    /// DXF's y axis points up, so all coordinates are flipped relative to the page.
+    fn point(&self, (x, y): Point) -> dxf::Point {
+        dxf::Point::new(x, self.options.page_height - y, 0.0)
+    }
+
+    fn polyline(&self, points: &[Point]) -> dxf::entities::LwPolyline {
+        dxf::entities::LwPolyline {
+            vertices: points
+                .iter()
+                .map(|&(x, y)| {
+                    dxf::LwPolylineVertex {
+                        x,
+                        y: self.options.page_height - y,
+                        ..Default::default()
+                    }
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(feature = "dxf")]
+impl<'a> Renderer for DxfRenderer<'a> {
+    fn begin_page(&mut self, _page_num: usize, _page: &Page) -> Result<(), Error> {
+        use dxf::enums::{AcadVersion, Units};
+        use dxf::tables::Layer;
+        let mut drawing = dxf::Drawing::new();
+        drawing.header.version = AcadVersion::R2004;
+        drawing.header.default_drawing_units = Units::Millimeters;
+        for name in &[Self::CUT_LAYER, Self::ENGRAVE_LAYER] {
+            drawing.add_layer(Layer {
+                name: name.to_string(),
+                ..Default::default()
+            });
+        }
+        self.drawing = Some(drawing);
+        Ok(())
+    }
+
+    fn end_page(&mut self, page_num: usize) -> Result<(), Error> {
+        let drawing = self.drawing.take().ok_or(Error::OutOfOrder(NO_PAGE))?;
+        let mut output = (self.make_output_stream)(page_num)?;
+        drawing.save(&mut output).map_err(
+            |e| Error::Dxf(e.to_string()),
+        )
+    }
+
+    fn cut_outline(&mut self, outline: &[Point]) -> Result<(), Error> {
+        let options = self.options;
+        // Same kerf compensation as the PDF output.
+        let mut polyline = self.polyline(&grow_polygon(outline, options.cut_stroke_width / 2.0));
+        polyline.set_is_closed(true);
+        self.add_entity(
+            dxf::entities::EntityType::LwPolyline(polyline),
+            Self::CUT_LAYER,
+            &options.cut_color,
+        )
+    }
+
+    fn cut_hole(&mut self, hole: &Hole) -> Result<(), Error> {
+        let options = self.options;
+        let circle = dxf::entities::Circle::new(
+            self.point(hole.position),
+            // Same kerf compensation as the SVG output.
+            options.hole_radius - (options.cut_stroke_width / 2.0),
+        );
+        self.add_entity(
+            dxf::entities::EntityType::Circle(circle),
+            Self::CUT_LAYER,
+            &options.cut_color,
+        )
+    }
+
+    fn engrave_line(&mut self, line: &GridLine) -> Result<(), Error> {
+        let options = self.options;
+        // A polyline rather than a line so that it can have a width.
+        let mut polyline = self.polyline(&[line.start, line.end]);
+        polyline.constant_width = options.grid_line_width(line);
+        self.add_entity(
+            dxf::entities::EntityType::LwPolyline(polyline),
+            Self::ENGRAVE_LAYER,
+            &options.engrave_color,
+        )
+    }
+
+    fn engrave_text(&mut self, text: &Text) -> Result<(), Error> {
+        let options = self.options;
+        let entity = dxf::entities::Text {
+            location: self.point(text.position),
+            text_height: text.font_size,
+            value: text.text.clone(),
+            ..Default::default()
+        };
+        self.add_entity(
+            dxf::entities::EntityType::Text(entity),
+            Self::ENGRAVE_LAYER,
+            &options.engrave_color,
+        )
+    }
+}
+
+/// Converts a MIDI note number to the numbering of `Options::notes`, which counts down from 128
+/// so that higher notes sort first, or back again.
+pub fn midi_note(note: u8) -> u8 {
+    128 - note
+}
+
+/// Returns the scientific pitch name (e.g. `C4` for middle C) of a note numbered as in
+/// `Options::notes`, or `?` for a note outside the MIDI range.
+pub fn note_name(note: u8) -> String {
+    const NAMES: [&str; 12] =
+        ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+    if !(1..=128).contains(&note) {
+        return "?".to_string();
+    }
+    let midi = midi_note(note) as i16;
+    format!("{}{}", NAMES[(midi % 12) as usize], midi / 12 - 1)
+}
+
+/// Shifts a note (numbered as in `Options::notes`) up by `shift` semitones, or returns `None` if
+/// the result is outside the MIDI range.
+fn transpose_note(note: u8, shift: i8) -> Option<u8> {
+    // Higher notes have lower numbers.
+    let transposed = note as i16 - shift as i16;
+    if (1..=128).contains(&transposed) {
+        Some(transposed as u8)
+    } else {
+        None
+    }
+}
+
+/// Returns the time signature changes (tick, bar length in beats) from all tracks, sorted by tick.
+fn time_signatures(smf: &SMF) -> Vec<TimeSignature> {
+    let mut changes = Vec::new();
+    for track in &smf.tracks {
+        let mut time = 0;
+        for event in &track.events {
+            time += event.vtime;
+            if let Event::Meta(ref meta) = event.event {
+                if meta.command == MetaCommand::TimeSignature && meta.data.len() >= 2 {
+                    // The denominator is stored as a power of 2. Skip signatures whose
+                    // denominator does not fit.
+                    if let Some(denominator) = 1u32.checked_shl(meta.data[1] as u32) {
+                        changes.push(TimeSignature {
+                            tick: time,
+                            numerator: meta.data[0],
+                            denominator,
+                        });
+                    }
+                }
+            }
+        }
+    }
+    changes.sort_by_key(|signature| signature.tick);
+    changes
+}
+
+/// Returns the time (in ticks) of every beat up to and including `last`, and whether it starts a
+/// bar. Time-code based files have no beats.
+fn beats(smf: &SMF, last: u64) -> Vec<(u64, bool)> {
+    if smf.division <= 0 {
+        return Vec::new();
+    }
+    let div = smf.division as u64;
+    let mut beats = Vec::new();
+    let mut bar_lines = bar_lines(smf).peekable();
+    while let Some(bar_start) = bar_lines.next() {
+        if bar_start > last {
+            break;
+        }
+        let bar_end = *bar_lines.peek().unwrap();
+        let mut beat = bar_start;
+        while beat < bar_end && beat <= last {
+            beats.push((beat, beat == bar_start));
+            beat += div;
+        }
+    }
+    beats
+}
+
+/// Iterates over the ticks of the bar lines of a song, which must not use time-code division.
+/// The bars go on after the end of the song.
+fn bar_lines(smf: &SMF) -> BarLines {
+    BarLines {
+        div: smf.division as f64,
+        signatures: time_signatures(smf),
+        next_signature: 0,
+        bar_start: 0.0,
+        bar_length: DEFAULT_BAR_LENGTH * smf.division as f64,
+    }
+}
+
+struct BarLines {
+    div: f64,
+    signatures: Vec<TimeSignature>,
+    next_signature: usize,
+    bar_start: f64,
+    bar_length: f64,
+}
+
+impl Iterator for BarLines {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        let bar_start = self.bar_start;
+        while let Some(signature) = self.signatures.get(self.next_signature) {
+            if signature.tick as f64 > bar_start {
+                break;
+            }
+            self.bar_length = (signature.beats_per_bar() * self.div).max(1.0);
+            self.next_signature += 1;
+        }
+        // Time signatures should only change at the start of a bar, so a change cuts the
+        // current bar short.
+        self.bar_start = match self.signatures.get(self.next_signature) {
+            Some(signature) => (bar_start + self.bar_length).min(signature.tick as f64),
+            None => bar_start + self.bar_length,
+        };
+        Some(bar_start.round() as u64)
+    }
+}
+
+/// Summarizes the tracks, length, tempo and time signatures of a MIDI file.
+pub fn midi_info(smf: &SMF) -> MidiInfo {
+    let changes = tempo_changes(smf);
+    let division = smf.division;
+    let beats = |ticks: u64| if division > 0 {
+        Some(ticks as f64 / division as f64)
+    } else {
+        None
+    };
+    let seconds = |ticks: u64| if division > 0 {
+        Some(ticks_to_micros(&changes, division as f64, ticks) as f64 / MICROS_PER_SECOND)
+    } else {
+        ticks_per_stretch_unit(division).ok().map(|per_second| ticks as f64 / per_second)
+    };
+    let mut tracks = Vec::new();
+    for track in &smf.tracks {
+        let mut info = TrackInfo {
+            name: None,
+            channels: Vec::new(),
+            notes: 0,
+            note_range: None,
+            duration: 0,
+            duration_beats: None,
+            duration_seconds: None,
+        };
+        for event in &track.events {
+            info.duration += event.vtime;
+            match event.event {
+                // Many sequencers encode note-off as NoteOn with velocity 0.
+                Event::Midi(ref msg) if msg.status() == Status::NoteOn && msg.data(2) > 0 => {
+                    let note = midi_note(msg.data(1));
+                    info.notes += 1;
+                    info.note_range = Some(match info.note_range {
+                        Some((highest, lowest)) => (highest.min(note), lowest.max(note)),
+                        None => (note, note),
+                    });
+                    let channel = msg.channel();
+                    match info.channels.iter().position(|&(c, _)| c == channel) {
+                        Some(i) => info.channels[i].1 += 1,
+                        None => info.channels.push((channel, 1)),
+                    }
+                }
+                Event::Meta(ref meta) if meta.command == MetaCommand::SequenceOrTrackName &&
+                                           info.name.is_none() => {
+                    info.name = Some(String::from_utf8_lossy(&meta.data).into_owned());
+                }
+                _ => {}
+            }
+        }
+        info.channels.sort();
+        info.duration_beats = beats(info.duration);
+        info.duration_seconds = seconds(info.duration);
+        tracks.push(info);
+    }
+    let duration = tracks.iter().map(|track| track.duration).max().unwrap_or(0);
+    MidiInfo {
+        division,
+        tracks,
+        duration,
+        duration_beats: beats(duration),
+        duration_seconds: seconds(duration),
+        tempo_changes: changes
+            .iter()
+            .map(|&(tick, tempo)| {
+                TempoChange {
+                    tick,
+                    beats_per_minute: 60.0 * MICROS_PER_SECOND / tempo as f64,
+                }
+            })
+            .collect(),
+        time_signatures: time_signatures(smf),
+    }
+}
+
+/// Returns the tempo changes (tick, microseconds per beat) from all tracks, sorted by tick.
+fn tempo_changes(smf: &SMF) -> Vec<(u64, u64)> {
+    let mut changes = Vec::new();
+    for track in &smf.tracks {
+        let mut time = 0;
+        for event in &track.events {
+            time += event.vtime;
+            if let Event::Meta(ref meta) = event.event {
+                if meta.command == MetaCommand::TempoSetting && meta.data.len() >= 3 {
+                    changes.push((time, meta.data_as_u64(3)));
+                }
+            }
+        }
+    }
+    // Stable sort so that the last of several changes at the same tick wins.
+    changes.sort_by_key(|&(time, _)| time);
+    changes
+}
+
+/// Converts a time in ticks to microseconds since the start of the song.
+fn ticks_to_micros(tempo_changes: &[(u64, u64)], div: f64, ticks: u64) -> u64 {
+    let mut micros = 0.0;
+    let mut last_time = 0;
+    let mut tempo = DEFAULT_TEMPO;
+    for &(time, new_tempo) in tempo_changes {
+        if time >= ticks {
+            break;
+        }
+        micros += (time - last_time) as f64 * tempo as f64 / div;
+        last_time = time;
+        tempo = new_tempo;
+    }
+    micros += (ticks - last_time) as f64 * tempo as f64 / div;
+    micros.round() as u64
+}
+
+/// Returns the number of ticks per unit of `stretch`: per beat for metrical MIDI files and per
+/// second for SMPTE time-code files.
+fn ticks_per_stretch_unit(division: i16) -> Result<f64, Error> {
+    if division > 0 {
+        return Ok(division as f64);
+    }
+    // The upper byte is the negated frame rate and the lower byte is the ticks per frame.
+    let frames_per_second = match (division >> 8) as i8 {
+        -24 => 24.0,
+        -25 => 25.0,
+        // 30 drop-frame, i.e. NTSC video.
+        -29 => 30000.0 / 1001.0,
+        -30 => 30.0,
+        _ => return Err(Error::UnsupportedDiv),
+    };
+    let ticks_per_frame = (division & 0xff) as f64;
+    if ticks_per_frame == 0.0 {
+        return Err(Error::UnsupportedDiv);
+    }
+    Ok(frames_per_second * ticks_per_frame)
+}
+
+/// Turns an error from `render` back into the `io::Error` the `Options::make_*` methods return.
+fn io_error(e: Error) -> io::Error {
+    match e {
+        Error::Io(e) => e,
+        e => io::Error::new(io::ErrorKind::Other, e.to_string()),
+    }
+}
+
+/// A byte buffer that stays readable after a clone of it is handed to a renderer.
+#[cfg(feature = "pdf")]
+#[derive(Clone, Default)]
+struct SharedBuffer(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+#[cfg(feature = "pdf")]
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Offsets every edge of a closed polygon outward by `distance`, mitering the corners.
+pub fn grow_polygon(points: &[Point], distance: f64) -> Vec<Point> {
+    let mut points = points.to_vec();
+    points.dedup();
+    if points.len() > 1 && points[0] == points[points.len() - 1] {
+        points.pop();
+    }
+    let n = points.len();
+    if n < 3 {
+        return points;
+    }
+    // Which side of each edge is "outward" depends on the winding direction.
+    let twice_area: f64 = (0..n)
+        .map(|i| {
+            let (x1, y1) = points[i];
+            let (x2, y2) = points[(i + 1) % n];
+            x1 * y2 - x2 * y1
+        })
+        .sum();
+    let sign = if twice_area < 0.0 { 1.0 } else { -1.0 };
+    let normal = |a: Point, b: Point| {
+        let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+        let len = (dx * dx + dy * dy).sqrt();
+        (sign * -dy / len, sign * dx / len)
+    };
+    (0..n)
+        .map(|i| {
+            let prev = points[(i + n - 1) % n];
+            let cur = points[i];
+            let next = points[(i + 1) % n];
+            let n1 = normal(prev, cur);
+            let n2 = normal(cur, next);
+            let scale = distance / (1.0 + n1.0 * n2.0 + n1.1 * n2.1);
+            (cur.0 + (n1.0 + n2.0) * scale, cur.1 + (n1.1 + n2.1) * scale)
+        })
+        .collect()
+}
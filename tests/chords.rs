@@ -0,0 +1,62 @@
+extern crate lasermidi;
+extern crate rimd;
+
+use lasermidi::*;
+use rimd::{MidiMessage, SMF, SMFBuilder};
+
+// A five note chord (G3, C4, E4, G4, C5) followed by a single C4.
+fn song() -> SMF {
+    let mut builder = SMFBuilder::new();
+    builder.add_track();
+    for &note in &[55, 60, 64, 67, 72] {
+        builder.add_midi_abs(0, 0, MidiMessage::note_on(note, 100, 0));
+        builder.add_midi_abs(0, 240, MidiMessage::note_off(note, 0, 0));
+    }
+    builder.add_midi_abs(0, 480, MidiMessage::note_on(60, 100, 0));
+    builder.add_midi_abs(0, 720, MidiMessage::note_off(60, 0, 0));
+    let mut smf = builder.result();
+    smf.division = 480;
+    smf
+}
+
+// Returns the MIDI note numbers kept from the chord, from the highest down, and the number of
+// notes after it.
+fn thin(max_polyphony: Option<usize>, strategy: ChordStrategy) -> (Vec<u8>, usize) {
+    let options = Options {
+        tracks: Some(vec![0]),
+        max_polyphony,
+        chord_strategy: strategy,
+        ..Options::default()
+    };
+    let notes = options.selected_notes(&song()).unwrap();
+    let chord = notes
+        .iter()
+        .filter(|note| note.tick == 0)
+        .map(|note| note.midi_note())
+        .collect();
+    (chord, notes.iter().filter(|note| note.tick > 0).count())
+}
+
+#[test]
+fn unlimited_polyphony_keeps_every_note() {
+    assert_eq!(
+        thin(None, ChordStrategy::Melody),
+        (vec![72, 67, 64, 60, 55], 1)
+    );
+}
+
+#[test]
+fn highest_keeps_top_notes() {
+    assert_eq!(thin(Some(3), ChordStrategy::Highest), (vec![72, 67, 64], 1));
+}
+
+#[test]
+fn lowest_keeps_bottom_notes() {
+    assert_eq!(thin(Some(3), ChordStrategy::Lowest), (vec![64, 60, 55], 1));
+}
+
+#[test]
+fn melody_keeps_top_and_bass_then_fills_from_top() {
+    assert_eq!(thin(Some(3), ChordStrategy::Melody), (vec![72, 67, 55], 1));
+    assert_eq!(thin(Some(2), ChordStrategy::Melody), (vec![72, 55], 1));
+}
@@ -0,0 +1,73 @@
+extern crate lasermidi;
+extern crate rimd;
+
+use lasermidi::*;
+use rimd::{MidiMessage, SMF, SMFBuilder};
+
+// Plays each (tick, MIDI note) on one track.
+fn song(notes: &[(u64, u8)]) -> SMF {
+    let mut builder = SMFBuilder::new();
+    builder.add_track();
+    for &(tick, note) in notes {
+        builder.add_midi_abs(0, tick, MidiMessage::note_on(note, 100, 0));
+        builder.add_midi_abs(0, tick + 10, MidiMessage::note_off(note, 0, 0));
+    }
+    let mut smf = builder.result();
+    smf.division = 480;
+    smf
+}
+
+fn options() -> Options {
+    Options {
+        tracks: Some(vec![0]),
+        min_hole_distance: 10.0,
+        ..Options::default()
+    }
+}
+
+#[test]
+fn collisions_across_strips_are_found() {
+    // At 16mm per beat the first strip holds 235.8mm of notes. These two C4s are 9mm apart at
+    // 233.8mm and 242.8mm, so neither strip has both holes on it.
+    let smf = song(&[(0, 64), (7014, 60), (7284, 60)]);
+    let (pages, report) = options().layout_with_report(smf).unwrap();
+    assert_eq!(pages[0].strips.len(), 2);
+    assert_eq!(report.collisions.len(), 1);
+    let collision = report.collisions[0];
+    assert_eq!(collision.strip, 0);
+    assert!((collision.distance - 9.0).abs() < 1e-9, "distance {}", collision.distance);
+    assert!((collision.second.0 - collision.first.0 - 9.0).abs() < 1e-9);
+}
+
+#[test]
+fn strict_fails_on_collisions_across_strips() {
+    let smf = song(&[(0, 64), (7014, 60), (7284, 60)]);
+    let options = Options {
+        strict: true,
+        ..options()
+    };
+    match options.layout(smf) {
+        Err(Error::HoleCollisions(1)) => {}
+        other => panic!("expected one collision, got {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn collisions_across_loop_seam_are_found() {
+    // An eight beat (128mm) loop with a C4 8mm before the seam and another right after it.
+    let smf = song(&[(0, 60), (1920, 64), (3600, 60)]);
+    let options = Options {
+        loop_padding: Some(LoopPadding::Beat),
+        ..options()
+    };
+    let (_, report) = options.layout_with_report(smf).unwrap();
+    assert_eq!(report.collisions.len(), 1);
+    assert!((report.collisions[0].distance - 8.0).abs() < 1e-9);
+}
+
+#[test]
+fn holes_far_apart_do_not_collide() {
+    let smf = song(&[(0, 60), (1920, 64), (3600, 60)]);
+    let (_, report) = options().layout_with_report(smf).unwrap();
+    assert!(report.collisions.is_empty());
+}
@@ -0,0 +1,58 @@
+extern crate lasermidi;
+extern crate toml;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+
+use lasermidi::*;
+
+// Options that differ from the defaults in every kind of field.
+fn options() -> Options {
+    Options {
+        tracks: None,
+        channels: Some(vec![1, 10]),
+        missing_notes: MissingNotePolicy::Nearest,
+        max_polyphony: Some(2),
+        chord_strategy: ChordStrategy::Melody,
+        hole_labels: Some(LabelStyle::Solfege),
+        quantize: Some(Grid::NoteValue(16)),
+        start: Some(Position::Bar(3)),
+        end: Some(Position::Tick(7680)),
+        join_style: JoinStyle::Diagonal,
+        loop_padding: Some(LoopPadding::Bar),
+        font_file: Some("font.ttf".to_string()),
+        title: "Round trip".to_string(),
+        stretch: 12.5,
+        ..Options::default()
+    }
+}
+
+// Writes `contents` to a file named `name` in the temporary directory and loads it back.
+fn load(name: &str, contents: &str) -> Options {
+    let path = env::temp_dir().join(format!("lasermidi-{}-{}", std::process::id(), name));
+    File::create(&path).unwrap().write_all(contents.as_bytes()).unwrap();
+    let options = Options::load(&path);
+    fs::remove_file(&path).unwrap();
+    options.unwrap()
+}
+
+#[test]
+fn printed_config_loads_back_unchanged() {
+    let printed = toml::to_string(&options()).unwrap();
+    let loaded = load("config.toml", &printed);
+    assert_eq!(toml::to_string(&loaded).unwrap(), printed);
+}
+
+#[test]
+fn fields_left_out_keep_their_defaults() {
+    let loaded = load("partial.toml", "title = \"Partial\"\n");
+    assert_eq!(loaded.title, "Partial");
+    assert_eq!(
+        toml::to_string(&Options {
+            title: String::new(),
+            ..loaded
+        }).unwrap(),
+        toml::to_string(&Options::default()).unwrap()
+    );
+}
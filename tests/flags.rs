@@ -0,0 +1,97 @@
+extern crate docopt;
+extern crate lasermidi;
+extern crate rimd;
+
+use docopt::Docopt;
+use lasermidi::*;
+use rimd::{MidiMessage, SMF, SMFBuilder};
+
+fn flags(argv: &[&str]) -> Flags {
+    let usage = "Usage:\n    lasermidi [options]\n\nOptions:".to_string() + FLAGS_USAGE;
+    Docopt::new(usage)
+        .unwrap()
+        .argv(Some("lasermidi").into_iter().chain(argv.iter().cloned()))
+        .deserialize()
+        .unwrap()
+}
+
+// C major from C#4, one semitone above the default notes.
+fn song() -> SMF {
+    let mut builder = SMFBuilder::new();
+    builder.add_track();
+    for (i, &note) in [61, 63, 65, 66, 68, 70, 72, 73].iter().enumerate() {
+        let tick = i as u64 * 480;
+        builder.add_midi_abs(0, tick, MidiMessage::note_on(note, 100, 0));
+        builder.add_midi_abs(0, tick + 10, MidiMessage::note_off(note, 0, 0));
+    }
+    let mut smf = builder.result();
+    smf.division = 480;
+    smf
+}
+
+#[test]
+fn auto_transposition_picks_the_best_shift() {
+    let (options, transpositions) = flags(&["-t", "0", "--transpose", "auto"])
+        .resolve(Some(&song()))
+        .unwrap();
+    assert_eq!(options.transpose, -1);
+    assert_eq!(transpositions.len(), 25);
+    assert_eq!(transpositions[11], Transposition { shift: -1, unplayable: 0 });
+}
+
+#[test]
+fn notes_from_the_song_replace_the_notes_list() {
+    let (options, transpositions) =
+        flags(&["-t", "0", "--notes", "song"]).resolve(Some(&song())).unwrap();
+    assert!(transpositions.is_empty());
+    let mut notes = options.notes.clone();
+    notes.sort();
+    assert_eq!(notes, vec![55, 56, 58, 60, 62, 63, 65, 67]);
+}
+
+#[test]
+fn resolving_against_the_song_needs_a_song() {
+    for argv in &[["--transpose", "auto"], ["--notes", "song"]] {
+        match flags(argv).resolve(None) {
+            Err(Error::InvalidFlag(_)) => {}
+            other => panic!("expected an invalid flag error, got {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn report_warnings_describe_each_entry() {
+    let report = Report {
+        substitutions: vec![
+            Substitution {
+                tick: 480,
+                original: 67,
+                replacement: Some(68),
+            },
+            Substitution {
+                tick: 960,
+                original: 20,
+                replacement: None,
+            },
+        ],
+        dropped_notes: vec![DroppedNote { tick: 0, note: 60 }],
+        collisions: Vec::new(),
+    };
+    assert_eq!(
+        report.warnings(),
+        vec![
+            "note 67 at tick 480 replaced with 68",
+            "note 20 at tick 960 dropped",
+            "note 60 at tick 0 dropped from chord",
+        ]
+    );
+}
+
+#[test]
+fn track_num_is_an_alias_of_tracks() {
+    assert_eq!(flags(&["--track-num", "2"]).options().unwrap().tracks, Some(vec![2]));
+    assert_eq!(
+        flags(&["--tracks", "0,1", "--track-num", "2"]).options().unwrap().tracks,
+        Some(vec![0, 1])
+    );
+}
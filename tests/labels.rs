@@ -0,0 +1,55 @@
+extern crate lasermidi;
+extern crate rimd;
+
+use lasermidi::*;
+use rimd::{MidiMessage, SMF, SMFBuilder};
+
+// Plays C4 on every beat for long enough to need several strips.
+fn song() -> SMF {
+    let mut builder = SMFBuilder::new();
+    builder.add_track();
+    for beat in 0..40 {
+        builder.add_midi_abs(0, beat * 480, MidiMessage::note_on(60, 100, 0));
+    }
+    let mut smf = builder.result();
+    smf.division = 480;
+    smf
+}
+
+fn options() -> Options {
+    Options {
+        tracks: Some(vec![0]),
+        note_labels: true,
+        ..Options::default()
+    }
+}
+
+#[test]
+fn only_the_first_strip_is_labeled_before_its_first_hole() {
+    let pages = options().layout(song()).unwrap();
+    let strips: Vec<&Strip> = pages.iter().flat_map(|page| page.strips.iter()).collect();
+    assert!(strips.len() > 1);
+    let first = strips[0];
+    assert_eq!(first.texts.len(), Options::default().notes.len());
+    let first_hole = first.holes.iter().map(|hole| hole.position.0).fold(f64::MAX, f64::min);
+    let hole_radius = Options::default().hole_radius;
+    for text in &first.texts {
+        // No label is wider than the first hole is far away.
+        assert!(text.position.0 + 3.0 * 0.6 * text.font_size <= first_hole - hole_radius);
+    }
+    for strip in &strips[1..] {
+        assert!(strip.texts.is_empty());
+    }
+}
+
+#[test]
+fn labels_need_space_before_the_first_note() {
+    let options = Options {
+        interior_margin_left: 2.0,
+        ..options()
+    };
+    match options.validate() {
+        Err(Error::InvalidOptions(problems)) => assert_eq!(problems.len(), 1),
+        other => panic!("expected invalid options, got {:?}", other),
+    }
+}
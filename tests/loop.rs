@@ -0,0 +1,83 @@
+extern crate lasermidi;
+extern crate rimd;
+
+use lasermidi::*;
+use rimd::{MetaEvent, MidiMessage, SMF, SMFBuilder};
+
+// A C major scale in quarter notes on one track.
+fn song() -> SMF {
+    let mut builder = SMFBuilder::new();
+    builder.add_track();
+    for (i, &note) in [60, 62, 64, 65, 67, 69, 71, 72].iter().enumerate() {
+        let time = i as u64 * 480;
+        builder.add_midi_abs(0, time, MidiMessage::note_on(note, 100, 0));
+        builder.add_midi_abs(0, time + 240, MidiMessage::note_off(note, 0, 0));
+    }
+    let mut smf = builder.result();
+    smf.division = 480;
+    smf
+}
+
+#[test]
+fn loop_holes_lie_within_outline() {
+    for &stretch in &[16.0, 20.0, 37.0, 60.0, 100.0] {
+        let options = Options {
+            tracks: Some(vec![0]),
+            stretch,
+            loop_padding: Some(LoopPadding::Beat),
+            ..Options::default()
+        };
+        let pages = options.layout(song()).unwrap();
+        for strip in pages.iter().flat_map(|page| page.strips.iter()) {
+            let min_x = strip.outline.iter().map(|p| p.0).fold(f64::INFINITY, f64::min);
+            let max_x = strip.outline.iter().map(|p| p.0).fold(f64::NEG_INFINITY, f64::max);
+            for hole in &strip.holes {
+                let x = hole.position.0;
+                assert!(
+                    x - options.hole_radius < max_x && x + options.hole_radius > min_x,
+                    "hole at x={} outside outline {}..{} with stretch {}",
+                    x,
+                    min_x,
+                    max_x,
+                    stretch
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn loop_length_is_padded_to_whole_beats() {
+    let options = Options {
+        tracks: Some(vec![0]),
+        loop_padding: Some(LoopPadding::Beat),
+        ..Options::default()
+    };
+    let size = options.tape_size(&song()).unwrap();
+    assert_eq!(size.strips, 1);
+    // Eight beats at the default 16mm per beat.
+    assert!((size.length - 128.0).abs() < 1e-9, "length {}", size.length);
+}
+
+#[test]
+fn bar_padding_ends_on_a_bar_line_after_a_time_signature_change() {
+    // Two bars of 3/4 followed by 4/4, so the bar lines are at 0, 1440, 2880, 4800 and 6720.
+    let mut builder = SMFBuilder::new();
+    builder.add_track();
+    builder.add_meta_abs(0, 0, MetaEvent::time_signature(3, 2, 24, 8));
+    builder.add_meta_abs(0, 2880, MetaEvent::time_signature(4, 2, 24, 8));
+    for beat in 0..10 {
+        builder.add_midi_abs(0, beat * 480, MidiMessage::note_on(60, 100, 0));
+        builder.add_midi_abs(0, beat * 480 + 240, MidiMessage::note_off(60, 0, 0));
+    }
+    let mut smf = builder.result();
+    smf.division = 480;
+    let options = Options {
+        tracks: Some(vec![0]),
+        loop_padding: Some(LoopPadding::Bar),
+        ..Options::default()
+    };
+    // The last note is at 4320, so the loop ends at the bar line at 4800: ten beats.
+    let size = options.tape_size(&smf).unwrap();
+    assert!((size.length - 160.0).abs() < 1e-9, "length {}", size.length);
+}
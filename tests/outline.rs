@@ -0,0 +1,45 @@
+extern crate lasermidi;
+
+use lasermidi::*;
+
+fn assert_points_eq(actual: &[Point], expected: &[Point]) {
+    assert_eq!(actual.len(), expected.len(), "{:?}", actual);
+    for (a, e) in actual.iter().zip(expected) {
+        assert!(
+            (a.0 - e.0).abs() < 1e-9 && (a.1 - e.1).abs() < 1e-9,
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+    }
+}
+
+#[test]
+fn squares_grow_outward_in_either_winding() {
+    let square = [(0.0, 0.0), (2.0, 0.0), (2.0, 2.0), (0.0, 2.0)];
+    let grown = [(-0.5, -0.5), (2.5, -0.5), (2.5, 2.5), (-0.5, 2.5)];
+    assert_points_eq(&grow_polygon(&square, 0.5), &grown);
+    let reversed: Vec<Point> = square.iter().rev().cloned().collect();
+    let grown_reversed: Vec<Point> = grown.iter().rev().cloned().collect();
+    assert_points_eq(&grow_polygon(&reversed, 0.5), &grown_reversed);
+}
+
+#[test]
+fn mitered_corners_keep_edges_parallel() {
+    // A right triangle whose hypotenuse moves out by 1 along its normal.
+    let grown = grow_polygon(&[(0.0, 0.0), (4.0, 0.0), (0.0, 4.0)], 1.0);
+    let sqrt2 = 2.0_f64.sqrt();
+    assert_points_eq(
+        &grown,
+        &[(-1.0, -1.0), (4.0 + 1.0 + sqrt2, -1.0), (-1.0, 4.0 + 1.0 + sqrt2)],
+    );
+}
+
+#[test]
+fn repeated_and_closing_points_are_ignored() {
+    let grown = grow_polygon(
+        &[(0.0, 0.0), (0.0, 0.0), (2.0, 0.0), (2.0, 2.0), (0.0, 2.0), (0.0, 0.0)],
+        0.5,
+    );
+    assert_points_eq(&grown, &[(-0.5, -0.5), (2.5, -0.5), (2.5, 2.5), (-0.5, 2.5)]);
+}
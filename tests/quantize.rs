@@ -0,0 +1,48 @@
+extern crate lasermidi;
+extern crate rimd;
+
+use lasermidi::*;
+use rimd::{MidiMessage, SMF, SMFBuilder};
+
+// Plays C4 at each of `ticks` on one track.
+fn song(ticks: &[u64]) -> SMF {
+    let mut builder = SMFBuilder::new();
+    builder.add_track();
+    for &tick in ticks {
+        builder.add_midi_abs(0, tick, MidiMessage::note_on(60, 100, 0));
+        builder.add_midi_abs(0, tick + 10, MidiMessage::note_off(60, 0, 0));
+    }
+    let mut smf = builder.result();
+    smf.division = 480;
+    smf
+}
+
+fn times(options: &Options, smf: &SMF) -> Vec<u64> {
+    options.selected_notes(smf).unwrap().iter().map(|note| note.time).collect()
+}
+
+#[test]
+fn quantize_snaps_to_nearest_grid_position() {
+    let options = Options {
+        tracks: Some(vec![0]),
+        quantize: Some(Grid::NoteValue(8)),
+        ..Options::default()
+    };
+    let smf = song(&[10, 250, 470, 735]);
+    assert_eq!(times(&options, &smf), vec![0, 240, 480, 720]);
+    // The original times are kept for reporting.
+    let ticks: Vec<u64> = options.selected_notes(&smf).unwrap().iter().map(|note| note.tick).collect();
+    assert_eq!(ticks, vec![10, 250, 470, 735]);
+}
+
+#[test]
+fn swing_delays_odd_grid_positions() {
+    let options = Options {
+        tracks: Some(vec![0]),
+        quantize: Some(Grid::Ticks(240)),
+        swing: 0.5,
+        ..Options::default()
+    };
+    let smf = song(&[10, 250, 470, 735]);
+    assert_eq!(times(&options, &smf), vec![0, 360, 480, 840]);
+}
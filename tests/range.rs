@@ -0,0 +1,87 @@
+extern crate lasermidi;
+extern crate rimd;
+
+use lasermidi::*;
+use rimd::{MetaEvent, MidiMessage, SMF, SMFBuilder};
+
+// Two bars of 4/4, two bars of 3/4, then 4/4 again, with C4 on every beat.
+fn song() -> SMF {
+    let mut builder = SMFBuilder::new();
+    builder.add_track();
+    builder.add_meta_abs(0, 0, MetaEvent::time_signature(4, 2, 24, 8));
+    builder.add_meta_abs(0, 3840, MetaEvent::time_signature(3, 2, 24, 8));
+    builder.add_meta_abs(0, 6720, MetaEvent::time_signature(4, 2, 24, 8));
+    for beat in 0..24 {
+        builder.add_midi_abs(0, beat * 480, MidiMessage::note_on(60, 100, 0));
+        builder.add_midi_abs(0, beat * 480 + 10, MidiMessage::note_off(60, 0, 0));
+    }
+    let mut smf = builder.result();
+    smf.division = 480;
+    smf
+}
+
+fn select(start: Position, end: Position) -> Vec<Note> {
+    let options = Options {
+        tracks: Some(vec![0]),
+        start: Some(start),
+        end: Some(end),
+        ..Options::default()
+    };
+    options.selected_notes(&song()).unwrap()
+}
+
+fn ticks(notes: &[Note]) -> Vec<u64> {
+    notes.iter().map(|note| note.tick).collect()
+}
+
+#[test]
+fn bar_positions_follow_time_signature_changes() {
+    assert_eq!(
+        ticks(&select(Position::Bar(2), Position::Bar(3))),
+        vec![1920, 2400, 2880, 3360]
+    );
+    assert_eq!(
+        ticks(&select(Position::Bar(4), Position::Bar(5))),
+        vec![5280, 5760, 6240]
+    );
+    assert_eq!(
+        ticks(&select(Position::Bar(6), Position::Bar(7))),
+        vec![8640, 9120, 9600, 10080]
+    );
+}
+
+#[test]
+fn times_are_measured_from_the_start_of_the_range() {
+    let notes = select(Position::Beat(3), Position::Tick(2400));
+    assert_eq!(ticks(&notes), vec![960, 1440, 1920]);
+    let times: Vec<u64> = notes.iter().map(|note| note.time).collect();
+    assert_eq!(times, vec![0, 480, 960]);
+}
+
+#[test]
+fn zero_bars_and_beats_are_rejected() {
+    for &position in &[Position::Bar(0), Position::Beat(0)] {
+        let options = Options {
+            tracks: Some(vec![0]),
+            start: Some(position),
+            ..Options::default()
+        };
+        assert!(options.validate().is_err());
+        // Without validation, 0 is treated as the first bar or beat.
+        assert_eq!(options.selected_notes(&song()).unwrap()[0].tick, 0);
+    }
+}
+
+#[test]
+fn notes_outside_the_midi_range_have_no_name() {
+    assert_eq!(note_name(68), "C4");
+    assert_eq!(note_name(0), "?");
+    assert_eq!(note_name(129), "?");
+}
+
+#[test]
+fn midi_note_numbering_is_its_own_inverse() {
+    assert_eq!(midi_note(60), 68);
+    assert_eq!(midi_note(68), 60);
+    assert_eq!(note_name(midi_note(69)), "A4");
+}
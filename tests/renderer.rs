@@ -0,0 +1,119 @@
+extern crate lasermidi;
+extern crate rimd;
+
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use lasermidi::*;
+use rimd::{MidiMessage, SMFBuilder};
+
+fn sink(_page_num: usize) -> io::Result<Box<dyn Write>> {
+    Ok(Box::new(io::sink()))
+}
+
+fn assert_out_of_order(result: Result<(), Error>) {
+    match result {
+        Err(Error::OutOfOrder(_)) => {}
+        other => panic!("expected an out of order error, got {:?}", other),
+    }
+}
+
+const OUTLINE: [Point; 3] = [(0.0, 0.0), (10.0, 0.0), (10.0, 10.0)];
+
+#[test]
+fn svg_drawing_outside_a_page_is_an_error() {
+    let options = Options::default();
+    let mut make_output_stream = sink;
+    let mut renderer = SvgRenderer::new(&options, &mut make_output_stream);
+    assert_out_of_order(renderer.cut_outline(&OUTLINE));
+    assert_out_of_order(renderer.end_page(0));
+    renderer.begin_page(0, &Page { strips: Vec::new() }).unwrap();
+    renderer.end_page(0).unwrap();
+    assert_out_of_order(renderer.cut_outline(&OUTLINE));
+}
+
+#[cfg(feature = "pdf")]
+#[test]
+fn pdf_drawing_outside_a_page_is_an_error() {
+    let options = Options::default();
+    let mut make_output_stream = sink;
+    let mut renderer = PdfRenderer::new(&options, &mut make_output_stream);
+    // The document starts with its first page open.
+    renderer.cut_outline(&OUTLINE).unwrap();
+    renderer.end_page(0).unwrap();
+    assert_out_of_order(renderer.cut_outline(&OUTLINE));
+    renderer.finish().unwrap();
+    assert_out_of_order(renderer.finish());
+    assert_out_of_order(renderer.begin_page(1, &Page { strips: Vec::new() }));
+}
+
+#[cfg(feature = "dxf")]
+#[test]
+fn dxf_drawing_outside_a_page_is_an_error() {
+    let options = Options::default();
+    let mut make_output_stream = sink;
+    let mut renderer = DxfRenderer::new(&options, &mut make_output_stream);
+    assert_out_of_order(renderer.cut_outline(&OUTLINE));
+    assert_out_of_order(renderer.end_page(0));
+    renderer.begin_page(0, &Page { strips: Vec::new() }).unwrap();
+    renderer.end_page(0).unwrap();
+    assert_out_of_order(renderer.cut_outline(&OUTLINE));
+}
+
+fn layout(options: &Options) -> Vec<Page> {
+    let mut builder = SMFBuilder::new();
+    builder.add_track();
+    for (i, &note) in [60, 64, 67, 72].iter().enumerate() {
+        builder.add_midi_abs(0, i as u64 * 480, MidiMessage::note_on(note, 100, 0));
+    }
+    let mut smf = builder.result();
+    smf.division = 480;
+    options.layout(smf).unwrap()
+}
+
+// Collects everything written to the streams it hands out.
+#[derive(Clone, Default)]
+struct Output(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn make_svg_is_the_same_as_rendering_svg() {
+    let options = Options {
+        tracks: Some(vec![0]),
+        ..Options::default()
+    };
+    let pages = layout(&options);
+    let made = Output::default();
+    options
+        .make_svg(&pages, &mut |_| Box::new(made.clone()))
+        .unwrap();
+    let rendered = Output::default();
+    {
+        let mut make_output_stream = |_| Ok(Box::new(rendered.clone()) as Box<dyn Write>);
+        render(&pages, &mut SvgRenderer::new(&options, &mut make_output_stream)).unwrap();
+    }
+    assert!(!made.0.borrow().is_empty());
+    assert_eq!(made.0.borrow()[..], rendered.0.borrow()[..]);
+}
+
+#[cfg(feature = "pdf")]
+#[test]
+fn make_pdf_writes_one_document() {
+    let options = Options {
+        tracks: Some(vec![0]),
+        ..Options::default()
+    };
+    let mut output = Vec::new();
+    options.make_pdf(&layout(&options), &mut output).unwrap();
+    assert!(output.starts_with(b"%PDF"));
+}
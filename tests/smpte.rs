@@ -0,0 +1,88 @@
+extern crate lasermidi;
+extern crate rimd;
+
+use lasermidi::*;
+use rimd::{MetaEvent, MidiMessage, SMF, SMFBuilder};
+
+// The division of a time-code based file: the negated frame rate in the upper byte and the ticks
+// per frame in the lower byte.
+fn smpte_division(frames_per_second: i8, ticks_per_frame: u8) -> i16 {
+    (((-frames_per_second) as u8 as u16) << 8 | ticks_per_frame as u16) as i16
+}
+
+// Plays C4 at each of `ticks`, with a tempo change that time-code files must ignore.
+fn song(division: i16, ticks: &[u64]) -> SMF {
+    let mut builder = SMFBuilder::new();
+    builder.add_track();
+    builder.add_meta_abs(0, 0, MetaEvent::tempo_setting(250000));
+    for &tick in ticks {
+        builder.add_midi_abs(0, tick, MidiMessage::note_on(60, 100, 0));
+        builder.add_midi_abs(0, tick + 10, MidiMessage::note_off(60, 0, 0));
+    }
+    let mut smf = builder.result();
+    smf.division = division;
+    smf
+}
+
+fn options() -> Options {
+    Options {
+        tracks: Some(vec![0]),
+        stretch: 10.0,
+        ..Options::default()
+    }
+}
+
+fn offsets(smf: SMF) -> Result<Vec<f64>, Error> {
+    let pages = options().layout(smf)?;
+    Ok(pages
+        .iter()
+        .flat_map(|page| page.strips.iter())
+        .flat_map(|strip| strip.holes.iter())
+        .map(|hole| hole.offset)
+        .collect())
+}
+
+#[test]
+fn stretch_is_in_mm_per_second() {
+    // 25 frames of 40 ticks is 1000 ticks per second.
+    let smf = song(smpte_division(25, 40), &[0, 500, 1000, 2500]);
+    assert_eq!(offsets(smf).unwrap(), vec![0.0, 5.0, 10.0, 25.0]);
+}
+
+#[test]
+fn drop_frame_runs_at_29_97_frames_per_second() {
+    // 2997 ticks is just short of a second, and times are kept in whole microseconds.
+    let smf = song(smpte_division(29, 100), &[0, 2997]);
+    let offsets = offsets(smf).unwrap();
+    assert!((offsets[1] - 9.99999).abs() < 1e-9, "{:?}", offsets);
+}
+
+#[test]
+fn unknown_frame_rates_are_unsupported() {
+    for &division in &[smpte_division(26, 40), smpte_division(25, 0)] {
+        match offsets(song(division, &[0])) {
+            Err(Error::UnsupportedDiv) => {}
+            other => panic!("expected an unsupported division, got {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn bars_and_beats_need_a_metrical_division() {
+    let options = Options {
+        start: Some(Position::Beat(2)),
+        ..options()
+    };
+    match options.layout(song(smpte_division(25, 40), &[0, 1000])) {
+        Err(Error::UnsupportedDiv) => {}
+        other => panic!("expected an unsupported division, got {:?}", other),
+    }
+}
+
+#[test]
+fn info_gives_durations_in_seconds_only() {
+    let info = midi_info(&song(smpte_division(24, 50), &[0, 2390]));
+    assert_eq!(info.duration, 2400);
+    assert_eq!(info.duration_beats, None);
+    assert_eq!(info.duration_seconds, Some(2.0));
+}
@@ -0,0 +1,83 @@
+extern crate lasermidi;
+extern crate rimd;
+
+use lasermidi::*;
+use rimd::{MidiMessage, SMF, SMFBuilder};
+
+// Plays each MIDI note on its own beat.
+fn song(notes: &[u8]) -> SMF {
+    let mut builder = SMFBuilder::new();
+    builder.add_track();
+    for (i, &note) in notes.iter().enumerate() {
+        builder.add_midi_abs(0, i as u64 * 480, MidiMessage::note_on(note, 100, 0));
+    }
+    let mut smf = builder.result();
+    smf.division = 480;
+    smf
+}
+
+// Lays out `song` on a box with the given MIDI notes and returns the substitutions made.
+fn substitutions(policy: MissingNotePolicy, midi_notes: &[u8], song: &[u8]) -> Vec<Substitution> {
+    let options = Options {
+        tracks: Some(vec![0]),
+        notes: midi_notes.iter().cloned().map(midi_note).collect(),
+        missing_notes: policy,
+        ..Options::default()
+    };
+    options.layout_with_report(self::song(song)).unwrap().1.substitutions
+}
+
+fn substitution(tick: u64, original: u8, replacement: Option<u8>) -> Substitution {
+    Substitution {
+        tick,
+        original: midi_note(original),
+        replacement: replacement.map(midi_note),
+    }
+}
+
+#[test]
+fn fold_moves_notes_by_the_fewest_octaves() {
+    assert_eq!(
+        substitutions(MissingNotePolicy::Fold, &[48, 60, 72], &[60, 84, 36]),
+        vec![substitution(480, 84, Some(72)), substitution(960, 36, Some(48))]
+    );
+}
+
+#[test]
+fn fold_drops_notes_with_no_octave_available() {
+    assert_eq!(
+        substitutions(MissingNotePolicy::Fold, &[60, 64], &[60, 62]),
+        vec![substitution(480, 62, None)]
+    );
+}
+
+#[test]
+fn fold_ties_go_to_the_lower_octave() {
+    assert_eq!(
+        substitutions(MissingNotePolicy::Fold, &[72, 48, 64], &[64, 60]),
+        vec![substitution(480, 60, Some(48))]
+    );
+}
+
+#[test]
+fn nearest_ties_go_to_the_lower_note() {
+    for notes in &[[60, 62], [62, 60]] {
+        assert_eq!(
+            substitutions(MissingNotePolicy::Nearest, notes, &[60, 61]),
+            vec![substitution(480, 61, Some(60))]
+        );
+    }
+}
+
+#[test]
+fn fail_reports_the_first_missing_note() {
+    let options = Options {
+        tracks: Some(vec![0]),
+        missing_notes: MissingNotePolicy::Fail,
+        ..Options::default()
+    };
+    match options.layout(song(&[60, 61])) {
+        Err(Error::InvalidNote(note)) => assert_eq!(note, midi_note(61)),
+        other => panic!("expected an invalid note error, got {:?}", other),
+    }
+}
@@ -0,0 +1,53 @@
+extern crate lasermidi;
+extern crate rimd;
+
+use lasermidi::*;
+use rimd::{MetaEvent, MidiMessage, SMF, SMFBuilder};
+
+// Plays C4 at each of `ticks` on one track, with tempo changes given as (tick, microseconds per
+// quarter note).
+fn song(ticks: &[u64], tempos: &[(u64, u32)]) -> SMF {
+    let mut builder = SMFBuilder::new();
+    builder.add_track();
+    for &(tick, tempo) in tempos {
+        builder.add_meta_abs(0, tick, MetaEvent::tempo_setting(tempo));
+    }
+    for &tick in ticks {
+        builder.add_midi_abs(0, tick, MidiMessage::note_on(60, 100, 0));
+        builder.add_midi_abs(0, tick + 10, MidiMessage::note_off(60, 0, 0));
+    }
+    let mut smf = builder.result();
+    smf.division = 480;
+    smf
+}
+
+fn times(smf: &SMF) -> Vec<u64> {
+    let options = Options {
+        tracks: Some(vec![0]),
+        tempo_map: true,
+        ..Options::default()
+    };
+    options.selected_notes(smf).unwrap().iter().map(|note| note.time).collect()
+}
+
+#[test]
+fn tempo_changes_are_applied_from_their_tick() {
+    // 120 bpm, then 240 bpm from the third beat.
+    let smf = song(&[0, 480, 960, 1440, 1920], &[(0, 500000), (960, 250000)]);
+    assert_eq!(times(&smf), vec![0, 500000, 1000000, 1250000, 1500000]);
+}
+
+#[test]
+fn default_tempo_applies_before_first_change() {
+    let smf = song(&[0, 480, 960], &[(480, 1000000)]);
+    assert_eq!(times(&smf), vec![0, 500000, 1500000]);
+}
+
+#[test]
+fn track_durations_follow_tempo_changes() {
+    let info = midi_info(&song(&[0, 1910], &[(0, 500000), (960, 250000)]));
+    let track = &info.tracks[0];
+    assert_eq!(track.duration, 1920);
+    assert_eq!(track.duration_beats, Some(4.0));
+    assert_eq!(track.duration_seconds, Some(1.5));
+}
@@ -0,0 +1,67 @@
+extern crate lasermidi;
+
+use std::f64;
+
+use lasermidi::*;
+
+fn problems(options: Options) -> Vec<String> {
+    match options.validate() {
+        Ok(()) => Vec::new(),
+        Err(Error::InvalidOptions(problems)) => problems,
+        Err(e) => panic!("expected invalid options, got {:?}", e),
+    }
+}
+
+#[test]
+fn default_options_are_valid() {
+    assert_eq!(problems(Options::default()), Vec::<String>::new());
+}
+
+#[test]
+fn every_problem_is_reported_at_once() {
+    let problems = problems(Options {
+        hole_radius: -1.0,
+        gap: f64::NAN,
+        notes: vec![0],
+        ..Options::default()
+    });
+    assert_eq!(problems.len(), 3, "{:?}", problems);
+    assert!(problems[0].contains("hole radius"));
+    assert!(problems[1].contains("space between strips"));
+    assert!(problems[2].contains("note 0"));
+}
+
+#[test]
+fn the_tape_must_fit_on_the_page() {
+    let page_height = Options::default().page_height;
+    let problems = problems(Options {
+        tape_height: page_height,
+        ..Options::default()
+    });
+    assert!(problems.iter().any(|p| p.contains("does not fit between the top and bottom margins")));
+}
+
+#[test]
+fn margins_must_leave_room_for_notes() {
+    let page_width = Options::default().page_width;
+    let problems = problems(Options {
+        margin_left: page_width / 2.0,
+        margin_right: page_width / 2.0,
+        ..Options::default()
+    });
+    assert_eq!(problems.len(), 3, "{:?}", problems);
+    for (problem, strip) in problems.iter().zip(&["first", "middle", "last"]) {
+        assert!(problem.ends_with(&format!("for notes on the {} strip of a tape", strip)));
+    }
+}
+
+#[test]
+fn rows_must_be_far_enough_apart_for_the_holes() {
+    let options = Options::default();
+    let problems = problems(Options {
+        row_spacing: options.hole_radius,
+        ..options
+    });
+    assert_eq!(problems.len(), 1, "{:?}", problems);
+    assert!(problems[0].contains("too close"));
+}
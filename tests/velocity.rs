@@ -0,0 +1,43 @@
+extern crate lasermidi;
+extern crate rimd;
+
+use lasermidi::*;
+use rimd::SMF;
+use std::path::Path;
+
+// note_off_encodings.mid has a single track containing:
+// - a note released with a real NoteOff,
+// - two notes released with NoteOn at velocity 0,
+// - a quiet (velocity 10) ghost note, also released with NoteOn at velocity 0.
+const FIXTURE: &str = "tests/fixtures/note_off_encodings.mid";
+
+fn options(min_velocity: u8) -> Options {
+    let notes: Vec<u8> = (40..81).collect();
+    Options {
+        tracks: Some(vec![0]),
+        min_velocity,
+        row_spacing: (68.6 - 6.0 - 5.0) / (notes.len() as f64 - 1.0),
+        notes,
+        ..Options::default()
+    }
+}
+
+fn count_holes(options: &Options) -> usize {
+    let smf = SMF::from_file(Path::new(FIXTURE)).unwrap();
+    let pages = options.layout(smf).unwrap();
+    pages
+        .iter()
+        .flat_map(|page| page.strips.iter())
+        .map(|strip| strip.holes.len())
+        .sum()
+}
+
+#[test]
+fn velocity_zero_note_on_is_note_off() {
+    assert_eq!(count_holes(&options(1)), 4);
+}
+
+#[test]
+fn min_velocity_drops_ghost_notes() {
+    assert_eq!(count_holes(&options(20)), 3);
+}